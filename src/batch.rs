@@ -1,223 +1,485 @@
 use crate::{
-    download::download_osm_files_for_all_tiles_if_needed,
-    lidar::generate_dem_and_vegetation_density_tiff_images_from_laz_file,
+    config::Config,
+    constants::BUFFER,
+    crs::get_epsg_code,
+    download::download_osm_file_if_needed,
+    error::{file_error, las_error, Error, Stage, StageResultExt},
+    lidar::generate_tile_rasters_if_needed,
     merge::merge_maps,
     png::generate_png_from_dem_vegetation_density_tiff_images_and_vector_file,
-    tile::{NeighborTiles, Tile, TileWithNeighbors},
+    points::is_lidar_file,
+    report::{BatchReport, SkippedFile},
+    tile::{Tile, TileWithNeighbors},
+    tile_index::TileIndex,
+    work_queue::{spawn_workers, WorkQueue},
 };
-use las::raw::Header;
+use las::{Header, Read, Reader};
 use std::{
-    collections::HashMap,
-    fs::{read_dir, File},
+    any::Any,
+    collections::{HashMap, HashSet},
+    fs::read_dir,
+    panic::{catch_unwind, AssertUnwindSafe},
     path::{Path, PathBuf},
-    sync::Arc,
-    thread::{sleep, spawn, JoinHandle},
-    time::Duration,
+    sync::{mpsc::channel, Arc},
 };
 
-pub fn batch(number_of_threads: usize, skip_lidar: bool, skip_vector: bool) {
+enum BatchEvent {
+    LidarDone(usize, Result<(), Error>),
+    Rendered(usize, Result<(), Error>),
+}
+
+/// Runs the LiDAR stage on every tile and renders each tile as soon as its own rasters
+/// and the ones of its neighbors are available, instead of waiting for the whole batch.
+pub fn batch(
+    lidar_threads: usize,
+    render_threads: usize,
+    skip_lidar: bool,
+    skip_vector: bool,
+    force: bool,
+    config: &Config,
+) -> Result<BatchReport, Error> {
     println!("Batch mode");
-    println!("Generating raw rasters for every tiles");
 
-    let tiles = get_tiles_with_neighbors();
-    let chunk_size = tiles.len() / number_of_threads;
-    let tiles_arc = Arc::new(tiles.clone());
+    let mut report = BatchReport::default();
+    let (tiles, skipped_files) =
+        get_tiles_with_neighbors(&config.input_dir, &config.output_dir, config.epsg_code)
+            .in_stage(Stage::TilesDiscovery)?;
 
-    if !skip_lidar {
-        let tiles_chunks: Vec<Vec<TileWithNeighbors>> = tiles_arc
-            .chunks(chunk_size)
-            .map(|chunk| chunk.to_vec())
-            .collect();
+    report.skipped.extend(skipped_files);
 
-        let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(chunk_size);
+    let tiles = Arc::new(tiles);
+    let neighbor_indexes = get_neighbor_indexes(&tiles);
+    let dependents = get_dependents(&neighbor_indexes);
+    let (sender, receiver) = channel::<BatchEvent>();
 
-        for chunk in tiles_chunks {
-            let chunk = Arc::new(chunk);
+    let lidar_queue = Arc::new(WorkQueue::<usize>::new());
+    let render_queue = Arc::new(WorkQueue::<(usize, TileWithNeighbors)>::new());
 
-            let spawned_thread = spawn(move || {
-                for tile in chunk.iter() {
-                    generate_dem_and_vegetation_density_tiff_images_from_laz_file(
-                        &tile.tile.laz_path,
-                        &tile.tile.dir_path,
-                    );
-                }
+    let lidar_tiles = tiles.clone();
+    let lidar_sender = sender.clone();
 
-                sleep(Duration::from_millis(1));
-            });
+    let lidar_config = config.clone();
 
-            handles.push(spawned_thread);
-        }
+    let mut handles = spawn_workers(&lidar_queue, lidar_threads, move |index| {
+        let tile = &lidar_tiles[index].tile;
+        println!("Generating raw rasters for {:?}", tile.dir_path);
+
+        let result = run_isolated(tile, Stage::Lidar, || {
+            generate_tile_rasters_if_needed(tile, force, &lidar_config)
+        });
+
+        let _ = lidar_sender.send(BatchEvent::LidarDone(index, result));
+    });
+
+    let render_config = config.clone();
+    let render_sender = sender.clone();
+
+    handles.extend(spawn_workers(
+        &render_queue,
+        render_threads,
+        move |(index, tile): (usize, TileWithNeighbors)| {
+            println!("Rendering {:?}", tile.tile.dir_path);
+
+            let result = render_tile(&tile, skip_vector, force, &render_config);
+            let _ = render_sender.send(BatchEvent::Rendered(index, result));
+        },
+    ));
+
+    drop(sender);
+
+    // None while the LiDAR stage of the tile is pending, then whether it succeeded
+    let mut lidar_statuses: Vec<Option<bool>> = vec![None; tiles.len()];
+    let mut scheduled = vec![false; tiles.len()];
+    let mut remaining_lidar = 0;
+    let mut remaining_render = 0;
+    let mut rendered_tiles: Vec<TileWithNeighbors> = vec![];
 
-        for handle in handles {
-            handle.join().unwrap();
+    if skip_lidar {
+        lidar_statuses = vec![Some(true); tiles.len()];
+    } else {
+        for index in 0..tiles.len() {
+            lidar_queue.push(index);
+            remaining_lidar += 1;
         }
     }
 
-    if !skip_vector {
-        download_osm_files_for_all_tiles_if_needed(&tiles);
+    lidar_queue.close();
+
+    if skip_lidar {
+        for index in 0..tiles.len() {
+            remaining_render += schedule_if_ready(
+                index,
+                &tiles,
+                &neighbor_indexes,
+                &lidar_statuses,
+                &mut scheduled,
+                &render_queue,
+            );
+        }
     }
 
-    let tiles_chunks: Vec<Vec<TileWithNeighbors>> = tiles_arc
-        .chunks(chunk_size)
-        .map(|chunk| chunk.to_vec())
-        .collect();
+    while remaining_lidar > 0 || remaining_render > 0 {
+        let Ok(event) = receiver.recv() else {
+            break;
+        };
 
-    let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(chunk_size);
+        match event {
+            BatchEvent::LidarDone(index, result) => {
+                remaining_lidar -= 1;
 
-    for chunk in tiles_chunks {
-        let chunk = Arc::new(chunk);
+                if let Err(error) = &result {
+                    report.add_failure(&tiles[index].tile, error);
+                }
 
-        let spawned_thread = spawn(move || {
-            for tile in chunk.iter() {
-                println!("{:?}", tile.tile.dir_path);
+                lidar_statuses[index] = Some(result.is_ok());
 
-                generate_png_from_dem_vegetation_density_tiff_images_and_vector_file(
-                    tile.tile.clone(),
-                    tile.neighbors.clone(),
-                    skip_vector,
-                );
+                for &candidate in [index].iter().chain(dependents[index].iter()) {
+                    remaining_render += schedule_if_ready(
+                        candidate,
+                        &tiles,
+                        &neighbor_indexes,
+                        &lidar_statuses,
+                        &mut scheduled,
+                        &render_queue,
+                    );
+                }
             }
+            BatchEvent::Rendered(index, result) => {
+                remaining_render -= 1;
 
-            sleep(Duration::from_millis(1));
-        });
+                match result {
+                    Ok(()) => {
+                        report.add_success(&tiles[index].tile);
+                        rendered_tiles.push(tiles[index].clone());
+                    }
+                    Err(error) => report.add_failure(&tiles[index].tile, &error),
+                }
+            }
+        }
 
-        handles.push(spawned_thread);
+        if remaining_lidar == 0 {
+            render_queue.close();
+        }
     }
 
+    render_queue.close();
+
     for handle in handles {
-        handle.join().unwrap();
+        // Panics are caught for every tile, so worker threads cannot panic themselves
+        handle.join().expect("Batch worker thread panicked");
     }
 
-    merge_maps(tiles);
+    // The tiles are done, so a failed merge is reported with them instead of discarding them
+    if let Err(error) = merge_maps(rendered_tiles, config).in_stage(Stage::Merge) {
+        report.merge_error = Some(error.full_message());
+    }
+
+    Ok(report)
 }
 
-pub fn get_tiles_with_neighbors() -> Vec<TileWithNeighbors> {
-    let paths = read_dir("in").unwrap();
-    let mut tiles: Vec<TileWithNeighbors> = vec![];
-    let mut tiles_map = HashMap::<(i64, i64, i64, i64), PathBuf>::new();
+fn render_tile(
+    tile: &TileWithNeighbors,
+    skip_vector: bool,
+    force: bool,
+    config: &Config,
+) -> Result<(), Error> {
+    if !skip_vector {
+        run_isolated(&tile.tile, Stage::Download, || {
+            download_osm_file_if_needed(
+                tile.tile.min_x,
+                tile.tile.min_y,
+                tile.tile.max_x,
+                tile.tile.max_y,
+                tile.tile.epsg_code,
+                &config.input_dir,
+            )
+        })?;
+    }
 
-    for dir_entry in paths {
-        let path = dir_entry.expect("Problem reading directory entry").path();
+    run_isolated(&tile.tile, Stage::Render, || {
+        generate_png_from_dem_vegetation_density_tiff_images_and_vector_file(
+            tile.tile.clone(),
+            tile.neighbors.clone(),
+            skip_vector,
+            force,
+            config,
+        )
+    })
+}
 
-        match path.extension() {
-            Some(extension) => {
-                if !path.is_file() || extension != "laz" {
-                    continue;
-                }
+/// For every tile, the indexes of its neighbors in the tiles list.
+fn get_neighbor_indexes(tiles: &[TileWithNeighbors]) -> Vec<Vec<usize>> {
+    let indexes: HashMap<&PathBuf, usize> = tiles
+        .iter()
+        .enumerate()
+        .map(|(index, tile)| (&tile.tile.dir_path, index))
+        .collect();
 
-                let mut file = File::open(&path).expect("Cound not open laz file");
-                let header = Header::read_from(&mut file).unwrap();
+    tiles
+        .iter()
+        .map(|tile| {
+            tile.neighbors
+                .iter()
+                .filter_map(|neighbor| indexes.get(&neighbor.dir_path).copied())
+                .collect()
+        })
+        .collect()
+}
 
-                tiles_map.insert(
-                    (
-                        header.min_x.round() as i64,
-                        header.min_y.round() as i64,
-                        header.max_x.round() as i64,
-                        header.max_y.round() as i64,
-                    ),
-                    path,
-                );
+/// For every tile, the indexes of the tiles having it as a neighbor.
+fn get_dependents(neighbor_indexes: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut dependents: Vec<Vec<usize>> = vec![vec![]; neighbor_indexes.len()];
+
+    for (index, neighbors) in neighbor_indexes.iter().enumerate() {
+        for &neighbor_index in neighbors {
+            dependents[neighbor_index].push(index);
+        }
+    }
+
+    dependents
+}
+
+/// Pushes a tile to the render queue once its LiDAR stage succeeded and the ones of all its
+/// neighbors are over. Returns the number of scheduled tiles (0 or 1).
+fn schedule_if_ready(
+    index: usize,
+    tiles: &[TileWithNeighbors],
+    neighbor_indexes: &[Vec<usize>],
+    lidar_statuses: &[Option<bool>],
+    scheduled: &mut [bool],
+    render_queue: &WorkQueue<(usize, TileWithNeighbors)>,
+) -> usize {
+    if scheduled[index] || lidar_statuses[index] != Some(true) {
+        return 0;
+    }
+
+    let mut failed_neighbors = HashSet::<PathBuf>::new();
+
+    for &neighbor_index in &neighbor_indexes[index] {
+        match lidar_statuses[neighbor_index] {
+            None => return 0,
+            Some(false) => {
+                failed_neighbors.insert(tiles[neighbor_index].tile.dir_path.clone());
             }
-            None => {}
+            Some(true) => {}
+        }
+    }
+
+    scheduled[index] = true;
+
+    // Neighbors without rasters are left out so they do not break the buffer of valid tiles
+    render_queue.push((
+        index,
+        remove_failed_neighbors(&tiles[index], &failed_neighbors),
+    ));
+
+    1
+}
+
+/// Runs a tile stage, turning a panic into an error so it does not abort the whole batch.
+fn run_isolated<F>(tile: &Tile, stage: Stage, task: F) -> Result<(), Error>
+where
+    F: FnOnce() -> Result<(), Error>,
+{
+    match catch_unwind(AssertUnwindSafe(task)) {
+        Ok(Err(error @ Error::Stage { .. })) => Err(error),
+        Ok(result) => result.for_tile(stage, tile),
+        Err(payload) => {
+            Err::<(), Error>(Error::Panicked(get_panic_message(payload))).for_tile(stage, tile)
         }
     }
+}
+
+fn get_panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+
+    "unknown panic".to_owned()
+}
+
+fn remove_failed_neighbors(
+    tile: &TileWithNeighbors,
+    failed_tiles: &HashSet<PathBuf>,
+) -> TileWithNeighbors {
+    TileWithNeighbors {
+        tile: tile.tile.clone(),
+        neighbors: tile
+            .neighbors
+            .iter()
+            .filter(|neighbor_tile| !failed_tiles.contains(&neighbor_tile.dir_path))
+            .cloned()
+            .collect(),
+    }
+}
 
-    for ((min_x, min_y, max_x, max_y), laz_path) in tiles_map.clone().into_iter() {
-        let width = max_x - min_x;
-        let height = max_y - min_y;
-        let dir_path = Path::new("out").join(format!("{}_{}_{}_{}", min_x, min_y, max_x, max_y));
+/// Lists the LiDAR files of the input directory with their neighbors.
+/// Files that cannot be read are returned separately with the reason they were skipped.
+pub fn get_tiles_with_neighbors(
+    input_dir: &Path,
+    output_dir: &Path,
+    config_epsg_code: Option<u16>,
+) -> Result<(Vec<TileWithNeighbors>, Vec<SkippedFile>), Error> {
+    let (tiles_map, skipped_files) = get_tiles_map(input_dir, config_epsg_code)?;
+    let tile_index = TileIndex::new(get_tiles(&tiles_map, output_dir));
+
+    let tiles = tile_index
+        .tiles()
+        .iter()
+        .map(|tile| TileWithNeighbors {
+            tile: tile.clone(),
+            neighbors: tile_index.get_neighbor_tiles(tile, BUFFER as i64),
+        })
+        .collect();
+
+    Ok((tiles, skipped_files))
+}
 
-        let tile = Tile {
-            laz_path,
-            dir_path,
+/// Turns the LiDAR files of a tiles map into tiles, each one with its own output directory.
+pub fn get_tiles(tiles_map: &TilesMap, output_dir: &Path) -> Vec<Tile> {
+    tiles_map
+        .iter()
+        .map(|(&(min_x, min_y, max_x, max_y), lidar_file)| Tile {
+            laz_path: lidar_file.path.clone(),
+            dir_path: output_dir.join(format!("{}_{}_{}_{}", min_x, min_y, max_x, max_y)),
             min_x,
             min_y,
             max_x,
             max_y,
+            epsg_code: lidar_file.epsg_code,
+        })
+        .collect()
+}
+
+/// A LiDAR file found in a directory, with the EPSG code of its CRS.
+#[derive(Debug, Clone)]
+pub struct LidarFile {
+    pub path: PathBuf,
+    pub epsg_code: u16,
+}
+
+pub type TilesMap = HashMap<(i64, i64, i64, i64), LidarFile>;
+
+/// Maps the bounds of every readable LiDAR file of a directory to the file.
+/// Other files are returned separately with the reason they were skipped, except the osm files
+/// downloaded in the same directory. All the files must share the same CRS.
+pub fn get_tiles_map(
+    input_dir: &Path,
+    config_epsg_code: Option<u16>,
+) -> Result<(TilesMap, Vec<SkippedFile>), Error> {
+    let paths = read_dir(input_dir).map_err(file_error(input_dir))?;
+    let mut skipped_files: Vec<SkippedFile> = vec![];
+    let mut tiles_map = TilesMap::new();
+
+    for dir_entry in paths {
+        let path = dir_entry.map_err(file_error(input_dir))?.path();
+
+        if !path.is_file() || is_osm_file(&path) {
+            continue;
+        }
+
+        if !is_lidar_file(&path) {
+            skipped_files.push(SkippedFile {
+                path,
+                reason: "Not a LiDAR file, expected a .las, .laz or .copc.laz extension"
+                    .to_string(),
+            });
+            continue;
+        }
+
+        let header = match read_header(&path) {
+            Ok(header) => header,
+            Err(error) => {
+                skipped_files.push(SkippedFile {
+                    path,
+                    reason: error.full_message(),
+                });
+                continue;
+            }
         };
 
-        tiles.push(TileWithNeighbors {
-            tile,
-            neighbors: NeighborTiles {
-                top: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    min_x,
-                    max_y,
-                    max_x,
-                    max_y + height,
-                ),
-                top_right: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    max_x,
-                    max_y,
-                    max_x + width,
-                    max_y + height,
-                ),
-                right: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    max_x,
-                    min_y,
-                    max_x + width,
-                    max_y,
-                ),
-                bottom_right: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    max_x,
-                    min_y - height,
-                    max_x + width,
-                    min_y,
-                ),
-                bottom: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    min_x,
-                    min_y - height,
-                    max_x,
-                    min_y,
-                ),
-                bottom_left: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    min_x - width,
-                    min_y - height,
-                    min_x,
-                    min_y,
-                ),
-                left: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    min_x - width,
-                    min_y,
-                    min_x,
-                    max_y,
-                ),
-                top_left: get_neighbor_tile_from_hash_map(
-                    &tiles_map,
-                    min_x - width,
-                    max_y,
-                    min_x,
-                    max_y + height,
-                ),
-            },
-        })
+        let epsg_code = match get_epsg_code(&path, &header, config_epsg_code) {
+            Ok(epsg_code) => epsg_code,
+            Err(error) => {
+                skipped_files.push(SkippedFile {
+                    path,
+                    reason: error.full_message(),
+                });
+                continue;
+            }
+        };
+
+        let bounds = header.bounds();
+        let bounds = (
+            bounds.min.x.round() as i64,
+            bounds.min.y.round() as i64,
+            bounds.max.x.round() as i64,
+            bounds.max.y.round() as i64,
+        );
+
+        match tiles_map.get(&bounds) {
+            // Keeping the first file in name order so the same one is processed on every run
+            Some(existing_file) if existing_file.path < path => {
+                skipped_files.push(SkippedFile {
+                    reason: format!("Same bounds as {:?}", existing_file.path),
+                    path,
+                });
+            }
+            _ => {
+                let lidar_file = LidarFile {
+                    path: path.clone(),
+                    epsg_code,
+                };
+
+                if let Some(existing_file) = tiles_map.insert(bounds, lidar_file) {
+                    skipped_files.push(SkippedFile {
+                        path: existing_file.path,
+                        reason: format!("Same bounds as {:?}", path),
+                    });
+                }
+            }
+        }
     }
 
-    return tiles;
+    check_same_crs(tiles_map.values())?;
+
+    Ok((tiles_map, skipped_files))
 }
 
-fn get_neighbor_tile_from_hash_map(
-    tiles_map: &HashMap<(i64, i64, i64, i64), PathBuf>,
-    min_x: i64,
-    min_y: i64,
-    max_x: i64,
-    max_y: i64,
-) -> Option<Tile> {
-    return match tiles_map.get(&(min_x, min_y, max_x, max_y)) {
-        Some(neighbor_path) => Some(Tile {
-            laz_path: neighbor_path.clone(),
-            dir_path: Path::new("out").join(format!("{}_{}_{}_{}", min_x, min_y, max_x, max_y)),
-            min_x,
-            min_y,
-            max_x,
-            max_y,
-        }),
-        None => None,
+fn is_osm_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("osm"))
+}
+
+/// Fails if the LiDAR files do not all have the same CRS, as their rasters and maps could not
+/// be put together.
+pub fn check_same_crs<'a>(
+    lidar_files: impl IntoIterator<Item = &'a LidarFile>,
+) -> Result<(), Error> {
+    let mut lidar_files = lidar_files.into_iter();
+
+    let Some(first_file) = lidar_files.next() else {
+        return Ok(());
     };
+
+    match lidar_files.find(|lidar_file| lidar_file.epsg_code != first_file.epsg_code) {
+        Some(other_file) => Err(Error::MixedCrs {
+            first_path: first_file.path.clone(),
+            first_epsg_code: first_file.epsg_code,
+            second_path: other_file.path.clone(),
+            second_epsg_code: other_file.epsg_code,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Reads the header of a LiDAR file, with its VLRs.
+fn read_header(path: &Path) -> Result<Header, Error> {
+    Reader::from_path(path)
+        .map(|reader| reader.header().clone())
+        .map_err(las_error(path))
 }
@@ -1,91 +1,78 @@
-use crate::tile::{NeighborTiles, Tile};
-use std::process::{Command, ExitStatus};
+use crate::{
+    error::Error,
+    raster::{Raster, NO_DATA_VALUE},
+    tile::{NeighborTiles, Tile},
+};
 
-pub fn create_tif_with_buffer(
+/// Mosaics the raster of a tile with the ones of all its neighbors, cropped to the tile extent
+/// grown by the buffer, on the grid of the tile raster. The cells no raster covers, like the ones
+/// of a neighbor whose raster is missing, are nodata.
+pub fn mosaic_with_buffer(
     tile: &Tile,
     neighbor_tiles: &NeighborTiles,
     buffer: i64,
     tif_filename_without_extension: &str,
-) {
-    let vrt_with_buffer_path = tile.dir_path.join(format!(
-        "{}-with-buffer.vrt",
-        tif_filename_without_extension
-    ));
+) -> Result<Raster, Error> {
+    let get_raster_path = |tile: &Tile| {
+        tile.dir_path
+            .join(format!("{}.tif", tif_filename_without_extension))
+    };
 
-    let raster_with_buffer_path = tile.dir_path.join(format!(
-        "{}-with-buffer.tif",
-        tif_filename_without_extension
-    ));
+    let tile_raster = Raster::read_geotiff(&get_raster_path(tile))?;
+    let mut neighbor_rasters: Vec<Raster> = vec![];
 
-    let tile_raster_path = tile
-        .dir_path
-        .join(format!("{}.tif", tif_filename_without_extension));
+    for neighbor_tile in neighbor_tiles {
+        let neighbor_raster_path = get_raster_path(neighbor_tile);
 
-    let mut rasters_paths: Vec<String> = vec![tile_raster_path
-        .to_str()
-        .expect("Failed to convert path to string")
-        .to_string()];
+        if !neighbor_raster_path.exists() {
+            eprintln!(
+                "Warning: neighbor raster {:?} is missing, its area will be nodata",
+                neighbor_raster_path
+            );
+            continue;
+        }
 
-    let neighbors = vec![
-        neighbor_tiles.top.as_ref(),
-        neighbor_tiles.top_right.as_ref(),
-        neighbor_tiles.right.as_ref(),
-        neighbor_tiles.bottom_right.as_ref(),
-        neighbor_tiles.bottom.as_ref(),
-        neighbor_tiles.bottom_left.as_ref(),
-        neighbor_tiles.left.as_ref(),
-        neighbor_tiles.top_left.as_ref(),
-    ];
+        neighbor_rasters.push(Raster::read_geotiff(&neighbor_raster_path)?);
+    }
 
-    for neighbor in neighbors {
-        if let Some(neighbor_tile) = neighbor {
-            let path = neighbor_tile
-                .dir_path
-                .join(format!("{}.tif", tif_filename_without_extension));
+    // Snapping the buffered extent to the grid of the tile raster, as the low resolution DEM can
+    // be one meter above the tile
+    let resolution = tile_raster.resolution;
+    let min_x = tile_raster.min_x
+        + (((tile.min_x - buffer) as f64 - tile_raster.min_x) / resolution).round() * resolution;
+    let max_y = tile_raster.max_y
+        - ((tile_raster.max_y - (tile.max_y + buffer) as f64) / resolution).round() * resolution;
+    let width = ((tile.max_x - tile.min_x + 2 * buffer) as f64 / resolution).round() as usize;
+    let height = ((tile.max_y - tile.min_y + 2 * buffer) as f64 / resolution).round() as usize;
 
-            if let Some(path_str) = path.to_str() {
-                rasters_paths.push(path_str.to_string());
-            } else {
-                eprintln!(
-                    "Failed to convert path to string for {:?}",
-                    neighbor_tile.dir_path
-                );
-            }
-        }
-    }
+    let nodata = tile_raster.nodata.unwrap_or(NO_DATA_VALUE);
+    let mut raster_with_buffer = Raster::new(
+        width,
+        height,
+        min_x,
+        max_y,
+        resolution,
+        Some(nodata),
+        nodata,
+    );
 
-    // First creating a GDAL Virtual Dataset
-    let gdalbuildvrt_output = Command::new("gdalbuildvrt")
-        .arg(&vrt_with_buffer_path.to_str().unwrap())
-        .args(&rasters_paths)
-        .arg("--quiet")
-        .output()
-        .expect("failed to execute gdal_contour command");
+    // When footprints overlap, the data of the tile wins over the one of its neighbors
+    let rasters: Vec<&Raster> = [&tile_raster]
+        .into_iter()
+        .chain(&neighbor_rasters)
+        .collect();
 
-    if !ExitStatus::success(&gdalbuildvrt_output.status) {
-        println!("{}", String::from_utf8(gdalbuildvrt_output.stderr).unwrap());
-    }
+    for row in 0..height {
+        let y = max_y - (row as f64 + 0.5) * resolution;
 
-    // Then outpouting croped tif with buffer
-    let gdal_translate_output = Command::new("gdal_translate")
-        .args([
-            "-projwin",
-            &(tile.min_x - buffer).to_string(),
-            &(tile.max_y + buffer).to_string(),
-            &(tile.max_x + buffer).to_string(),
-            &(tile.min_y - buffer).to_string(),
-        ])
-        .args(["-of", "GTiff"])
-        .arg(&vrt_with_buffer_path.to_str().unwrap())
-        .arg(&raster_with_buffer_path.to_str().unwrap())
-        .arg("--quiet")
-        .output()
-        .expect("failed to execute gdal_contour command");
+        for column in 0..width {
+            let x = min_x + (column as f64 + 0.5) * resolution;
 
-    if !ExitStatus::success(&gdal_translate_output.status) {
-        println!(
-            "{}",
-            String::from_utf8(gdal_translate_output.stderr).unwrap()
-        );
+            if let Some(value) = rasters.iter().find_map(|raster| raster.get_value_at(x, y)) {
+                raster_with_buffer.set(column, row, value);
+            }
+        }
     }
+
+    Ok(raster_with_buffer)
 }
@@ -5,6 +5,16 @@ use skia_safe::{
 use std::fs::File;
 use std::io::Write;
 use std::mem;
+use std::path::Path as FilePath;
+
+use crate::error::{file_error, Error};
+
+/// The direction of the lines left by `Canvas::erase_stripes`.
+#[derive(Clone, Copy)]
+pub enum StripesDirection {
+    Horizontal,
+    Vertical,
+}
 
 pub struct Canvas {
     surface: Surface,
@@ -112,13 +122,51 @@ impl Canvas {
         self.save();
     }
 
+    /// Erases evenly spaced stripes of `spacing` pixels from everything drawn so far, leaving lines
+    /// of `line_width` pixels between them. The canvas then keeps erasing whatever is drawn on it.
+    pub fn erase_stripes(&mut self, line_width: f32, spacing: f32, direction: StripesDirection) {
+        let width = self.surface.width() as f32;
+        let height = self.surface.height() as f32;
+        let interval = line_width + spacing;
+
+        let length = match direction {
+            StripesDirection::Horizontal => height,
+            StripesDirection::Vertical => width,
+        };
+
+        let number_of_stripes = length as u32 / interval as u32;
+        self.set_transparent_color();
+
+        for i in 0..number_of_stripes {
+            let start = i as f32 * interval;
+            let end = start + spacing;
+
+            let stripe = match direction {
+                StripesDirection::Horizontal => [
+                    (0., start),
+                    (width, start),
+                    (width, end),
+                    (0., end),
+                    (0., start),
+                ],
+                StripesDirection::Vertical => [
+                    (start, 0.),
+                    (end, 0.),
+                    (end, height),
+                    (start, height),
+                    (start, 0.),
+                ],
+            };
+
+            self.draw_filled_polygon(&stripe);
+        }
+    }
+
     #[inline]
-    pub fn data(&mut self) -> Data {
+    pub fn data(&mut self) -> Option<Data> {
         let image = self.surface.image_snapshot();
         let mut context = self.surface.direct_context();
-        image
-            .encode(context.as_mut(), EncodedImageFormat::PNG, None)
-            .unwrap()
+        image.encode(context.as_mut(), EncodedImageFormat::PNG, None)
     }
 
     #[inline]
@@ -132,20 +180,25 @@ impl Canvas {
     }
 
     #[inline]
-    pub fn save_as(&mut self, filename: &str) {
-        let d = self.data();
-        let mut file = File::create(filename).unwrap();
+    pub fn save_as(&mut self, filename: &FilePath) -> Result<(), Error> {
+        let d = self
+            .data()
+            .ok_or_else(|| Error::InvalidPng(filename.to_path_buf()))?;
+        let mut file = File::create(filename).map_err(file_error(filename))?;
         let bytes = d.as_bytes();
-        file.write_all(bytes).unwrap();
+        file.write_all(bytes).map_err(file_error(filename))?;
+        Ok(())
     }
 
     #[inline]
-    pub fn load_from(filename: &str) -> Canvas {
-        let data = Data::from_filename(filename).unwrap();
-        let image = Image::from_encoded(data).unwrap();
+    pub fn load_from(filename: &FilePath) -> Result<Canvas, Error> {
+        let data = Data::from_filename(filename)
+            .ok_or_else(|| Error::MissingFile(filename.to_path_buf()))?;
+        let image =
+            Image::from_encoded(data).ok_or_else(|| Error::InvalidPng(filename.to_path_buf()))?;
         let mut c = Canvas::new(image.width(), image.height());
         c.draw_image(image);
-        c
+        Ok(c)
     }
 
     #[inline]
@@ -1,19 +1,80 @@
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand};
 
 // Update the docs when modifying
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// Optional name to operate on
-    pub file_path: Option<String>,
+    #[command(subcommand)]
+    pub command: Command,
+    /// Directory containing the input LiDAR and osm files (overrides the config)
+    #[arg(long, global = true)]
+    pub input_dir: Option<String>,
+    /// Directory where the generated tiles and merged map are written (overrides the config)
+    #[arg(long, global = true)]
+    pub output_dir: Option<String>,
+    /// Run every stage again, even the ones already up to date in the tile manifests
+    #[arg(long, global = true)]
+    pub force: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Generate the map of a single LiDAR file
+    Tile {
+        laz_path: String,
+        /// Directory containing the LiDAR files of the neighbor tiles (defaults to the directory of the LiDAR file)
+        #[arg(long)]
+        neighbors_dir: Option<String>,
+        #[arg(long)]
+        skip_lidar: bool,
+        #[arg(long)]
+        skip_vector: bool,
+    },
+    /// Generate the DEM and vegetation rasters of a single LiDAR file
+    Lidar { laz_path: String },
+    /// Render the map of a tile directory generated by the lidar command
+    Render {
+        tile_dir: String,
+        #[arg(long)]
+        skip_vector: bool,
+    },
+    /// Download the osm file covering an area
+    Download {
+        /// Area to download: min_x,min_y,max_x,max_y
+        #[arg(long, num_args = 4, value_delimiter = ',', required = true)]
+        bbox: Vec<i64>,
+        /// EPSG code of the CRS of the area coordinates (defaults to the one of the config)
+        #[arg(long)]
+        epsg_code: Option<u16>,
+    },
+    /// Merge the maps of several tile directories into the output directory
+    Merge {
+        #[arg(required = true)]
+        tile_dirs: Vec<String>,
+    },
+    /// Process every LiDAR file of the input directory and merge the resulting maps
+    Batch(BatchArgs),
+    /// Write a default config.json file
+    Init,
+    /// Validate the config.json file without running the pipeline
+    CheckConfig,
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct BatchArgs {
     #[arg(long)]
     pub skip_lidar: bool,
     #[arg(long)]
     pub skip_vector: bool,
     #[arg(long)]
-    pub batch: bool,
-    #[arg(long)]
     pub threads: Option<usize>,
+    /// Number of tiles processed concurrently by the LiDAR stage (defaults to --threads)
+    #[arg(long)]
+    pub lidar_threads: Option<usize>,
+    /// Number of tiles rendered concurrently (defaults to --threads)
+    #[arg(long)]
+    pub render_threads: Option<usize>,
+    /// Write the batch report as JSON to this file
     #[arg(long)]
-    pub default_config: bool,
+    pub report: Option<String>,
 }
@@ -1,43 +1,40 @@
 use image::RgbaImage;
 use imageproc::drawing::draw_filled_ellipse_mut;
 use std::{
-    fs::File,
     io::{stdout, Write},
     time::Instant,
 };
-use tiff::decoder::{Decoder, DecodingResult};
 
 use crate::{
     config::Config,
     constants::{
         BLACK, BUFFER, CLIFF_THICKNESS_1, CLIFF_THICKNESS_2, DEM_BLOCK_SIZE, INCH, TRANSPARENT,
     },
+    error::{image_error, Error},
+    raster::Raster,
+    terrain::TerrainDerivatives,
     tile::Tile,
 };
 
-pub fn render_cliffs(tile: &Tile, image_width: u32, image_height: u32, config: &Config) {
+pub fn render_cliffs(
+    tile: &Tile,
+    image_width: u32,
+    image_height: u32,
+    config: &Config,
+) -> Result<(), Error> {
     print!("Rendering cliffs");
     let _ = stdout().flush();
     let start = Instant::now();
 
     let dem_block_size_pixel = DEM_BLOCK_SIZE as f32 * config.dpi_resolution / INCH;
 
-    let slopes_path = tile.dir_path.join("slopes.tif");
-    let slopes_tif_file = File::open(slopes_path).expect("Cannot find slopes tif image!");
-
-    let mut slopes_img_decoder = Decoder::new(slopes_tif_file).expect("Cannot create decoder");
-    slopes_img_decoder = slopes_img_decoder.with_limits(tiff::decoder::Limits::unlimited());
-
-    let (slopes_width, _) = slopes_img_decoder.dimensions().unwrap();
+    let dem = Raster::read_geotiff(&tile.dir_path.join("dem-with-buffer.tif"))?;
+    let slopes = TerrainDerivatives::new(&dem, config.terrain.smoothing_radius).slope();
     let mut cliffs_layer_canvas = RgbaImage::from_pixel(image_width, image_height, TRANSPARENT);
 
-    let DecodingResult::F32(image_data) = slopes_img_decoder.read_image().unwrap() else {
-        panic!("Cannot read band data")
-    };
-
-    for index in 0..image_data.len() {
-        let x = index % slopes_width as usize;
-        let y = index / slopes_width as usize;
+    for index in 0..slopes.pixels.len() {
+        let x = index % slopes.width;
+        let y = index / slopes.width;
 
         let x_pixel = ((x as i64 - BUFFER as i64) as f32 * dem_block_size_pixel) as i32;
         let y_pixel = ((y as i64 - BUFFER as i64) as f32 * dem_block_size_pixel) as i32;
@@ -50,13 +47,17 @@ pub fn render_cliffs(tile: &Tile, image_width: u32, image_height: u32, config: &
             continue;
         }
 
-        let slope = image_data[index];
+        let slope = slopes.pixels[index];
+
+        if slopes.is_nodata(slope) {
+            continue;
+        }
 
         let mut cliff_thickness: Option<f32> = None;
 
-        if slope > config.cliff_threshold_2 {
+        if slope > config.cliff_threshold_2 as f64 {
             cliff_thickness = Some(CLIFF_THICKNESS_2);
-        } else if slope > config.cliff_threshold_1 {
+        } else if slope > config.cliff_threshold_1 as f64 {
             cliff_thickness = Some(CLIFF_THICKNESS_1);
         }
 
@@ -77,9 +78,11 @@ pub fn render_cliffs(tile: &Tile, image_width: u32, image_height: u32, config: &
     let cliffs_path = tile.dir_path.join("cliffs.png");
 
     cliffs_layer_canvas
-        .save(cliffs_path)
-        .expect("could not save cliffs png");
+        .save(&cliffs_path)
+        .map_err(image_error(&cliffs_path))?;
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
+
+    Ok(())
 }
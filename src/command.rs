@@ -0,0 +1,33 @@
+use crate::error::Error;
+use std::process::{Command, Output};
+
+/// Runs an external command to completion, turning a non-zero exit status into an error.
+pub fn run_command(command: &mut Command) -> Result<Output, Error> {
+    let command_line = format_command(command);
+
+    let output = command.output().map_err(|source| Error::CommandSpawn {
+        command: command_line.clone(),
+        source,
+    })?;
+
+    if !output.status.success() {
+        return Err(Error::CommandFailed {
+            command: command_line,
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+
+    Ok(output)
+}
+
+fn format_command(command: &Command) -> String {
+    let mut command_line = command.get_program().to_string_lossy().into_owned();
+
+    for arg in command.get_args() {
+        command_line.push(' ');
+        command_line.push_str(&arg.to_string_lossy());
+    }
+
+    command_line
+}
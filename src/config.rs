@@ -1,9 +1,15 @@
+use crate::error::{file_error, Error};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
+    fmt,
     fs::{self, File},
-    io::Write,
+    io::{self, Write},
+    path::{Path, PathBuf},
 };
 
+pub const CONFIG_FILE_PATH: &str = "./config.json";
+
 const DEFAULT_YELLOW_THRESHOLD: f64 = 0.5; // Update the docs when modifying
 const DEFAULT_GREEN_THRESHOLD_1: f64 = 1.0; // Update the docs when modifying
 const DEFAULT_GREEN_THRESHOLD_2: f64 = 2.0; // Update the docs when modifying
@@ -11,15 +17,47 @@ const DEFAULT_GREEN_THRESHOLD_3: f64 = 3.0; // Update the docs when modifying
 const DEFAULT_CLIFF_THRESHOLD_1: f32 = 45.; // Update the docs when modifying
 const DEFAULT_CLIFF_THRESHOLD_2: f32 = 55.; // Update the docs when modifying
 const DEFAULT_DPI_RESOLUTION: f32 = 600.0; // Update the docs when modifying
+const DEFAULT_INPUT_DIR: &str = "in"; // Update the docs when modifying
+const DEFAULT_OUTPUT_DIR: &str = "out"; // Update the docs when modifying
+
+const DEFAULT_FORM_LINES_STEEPNESS: f64 = 0.37; // Update the docs when modifying
+const DEFAULT_FORM_LINES_ADDITIONAL_TAIL_LENGTH: f64 = 17.0; // Update the docs when modifying
+const DEFAULT_FORM_LINES_MIN_GAP_LENGTH: u32 = 30; // Update the docs when modifying
+const DEFAULT_FORM_LINES_DASH_LENGTH: f64 = 60.0; // Update the docs when modifying
+const DEFAULT_FORM_LINES_GAP_LENGTH: f64 = 12.0; // Update the docs when modifying
+
+const DEFAULT_CONTOURS_INTERVAL: f64 = 5.0; // Update the docs when modifying
+const DEFAULT_CONTOURS_INDEX_MULTIPLE: u32 = 5; // Update the docs when modifying
+const DEFAULT_CONTOURS_SMOOTHING: f64 = 0.7; // Update the docs when modifying
+const DEFAULT_CONTOURS_CURVINESS: f64 = 1.1; // Update the docs when modifying
+
+const DEFAULT_LIDAR_GROUND_CLASSES: [u8; 1] = [2]; // Update the docs when modifying
+const DEFAULT_LIDAR_SAMPLING_CELL_SIZE: f64 = 0.5; // Update the docs when modifying
+const DEFAULT_LIDAR_YELLOW_BAND: &str = "high-vegetation"; // Update the docs when modifying
+const DEFAULT_LIDAR_GREEN_BAND: &str = "medium-vegetation"; // Update the docs when modifying
+
+const DEFAULT_GROUND_CLASSIFICATION_MIN_GROUND_RATIO: f64 = 0.01; // Update the docs when modifying
+const DEFAULT_GROUND_CLASSIFICATION_CELL_SIZE: f64 = 1.0; // Update the docs when modifying
+const DEFAULT_GROUND_CLASSIFICATION_MAX_WINDOW_SIZE: f64 = 18.0; // Update the docs when modifying
+const DEFAULT_GROUND_CLASSIFICATION_SLOPE: f64 = 0.15; // Update the docs when modifying
+const DEFAULT_GROUND_CLASSIFICATION_ELEVATION_THRESHOLD: f64 = 0.5; // Update the docs when modifying
+
+const DEFAULT_DEM_FILL_MAX_DISTANCE: f64 = 100.0; // Update the docs when modifying
+
+const DEFAULT_TERRAIN_SMOOTHING_RADIUS: f64 = 0.0; // Update the docs when modifying
 
-const DEFAULT_FORM_LINES_THRESHOLD: f64 = 0.05; // Update the docs when modifying
-const DEFAULT_FORM_LINES_MIN_DISTANCE_TO_CONTOUR: f64 = 5.0; // Update the docs when modifying
-const DEFAULT_FORM_LINES_MAX_DISTANCE_TO_CONTOUR: f64 = 100.0; // Update the docs when modifying
-const DEFAULT_FORM_LINES_MIN_LENGTH: f64 = 10.0; // Update the docs when modifying
-const DEFAULT_FORM_LINES_MIN_GAP_LENGTH: f64 = 50.0; // Update the docs when modifying
-const DEFAULT_FORM_LINES_ADDITIONAL_TAIL_LENGTH: f64 = 15.0; // Update the docs when modifying
+const DEFAULT_UNDERGROWTH_BAND: &str = "undergrowth"; // Update the docs when modifying
+const DEFAULT_UNDERGROWTH_THRESHOLD_1: f64 = 1.0; // Update the docs when modifying
+const DEFAULT_UNDERGROWTH_THRESHOLD_2: f64 = 2.0; // Update the docs when modifying
+const DEFAULT_UNDERGROWTH_MAX_CANOPY_DENSITY: f64 = 5.0; // Update the docs when modifying
 
-#[derive(Serialize, Deserialize)]
+const DEFAULT_TREES_MIN_HEIGHT: f64 = 15.0; // Update the docs when modifying
+const DEFAULT_TREES_ISOLATION_DISTANCE: f64 = 20.0; // Update the docs when modifying
+
+// Raster names already used by the LiDAR stage
+const RESERVED_BAND_NAMES: [&str; 4] = ["dem", "dem-low-resolution", "point-density", "chm"];
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     #[serde(default = "default_yellow_threshold")]
     pub yellow_threshold: f64,
@@ -35,8 +73,27 @@ pub struct Config {
     pub cliff_threshold_2: f32,
     #[serde(default = "default_dpi_resolution")]
     pub dpi_resolution: f32,
-    // #[serde(default = "FormLineConfig::default")]
-    // pub form_lines: FormLineConfig,
+    #[serde(default = "default_input_dir")]
+    pub input_dir: PathBuf,
+    #[serde(default = "default_output_dir")]
+    pub output_dir: PathBuf,
+    /// The projected CRS of the LiDAR files, overriding the one declared in their headers
+    #[serde(default)]
+    pub epsg_code: Option<u16>,
+    #[serde(default)]
+    pub form_lines: FormLinesConfig,
+    #[serde(default)]
+    pub contours: ContoursConfig,
+    #[serde(default)]
+    pub lidar: LidarConfig,
+    #[serde(default)]
+    pub dem: DemConfig,
+    #[serde(default)]
+    pub terrain: TerrainConfig,
+    #[serde(default)]
+    pub undergrowth: UndergrowthConfig,
+    #[serde(default)]
+    pub trees: TreesConfig,
 }
 
 // #[derive(Serialize, Deserialize)]
@@ -79,45 +136,649 @@ pub struct Config {
 //     pub stairway_532: HashMap<String, String>,
 // }
 
-#[derive(Serialize, Deserialize)]
-pub struct _FormLineConfig {
-    #[serde(default = "default_form_lines_threshold")]
-    pub threshold: f64,
-    #[serde(default = "default_form_lines_min_distance_to_contour")]
-    pub min_distance_to_contour: f64,
-    #[serde(default = "default_form_lines_max_distance_to_contour")]
-    pub max_distance_to_contour: f64,
-    #[serde(default = "default_form_lines_min_length")]
-    pub min_length: f64,
-    #[serde(default = "default_form_lines_min_gap_length")]
-    pub min_gap_length: f64,
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FormLinesConfig {
+    #[serde(default = "default_form_lines_steepness")]
+    pub steepness: f64,
     #[serde(default = "default_form_lines_additional_tail_length")]
     pub additional_tail_length: f64,
+    #[serde(default = "default_form_lines_min_gap_length")]
+    pub min_gap_length: u32,
+    #[serde(default = "default_form_lines_dash_length")]
+    pub dash_length: f64,
+    #[serde(default = "default_form_lines_gap_length")]
+    pub gap_length: f64,
 }
 
-impl _FormLineConfig {
-    fn _default() -> Self {
+impl Default for FormLinesConfig {
+    fn default() -> Self {
         Self {
-            threshold: DEFAULT_FORM_LINES_THRESHOLD,
-            min_distance_to_contour: DEFAULT_FORM_LINES_MIN_DISTANCE_TO_CONTOUR,
-            max_distance_to_contour: DEFAULT_FORM_LINES_MAX_DISTANCE_TO_CONTOUR,
-            min_length: DEFAULT_FORM_LINES_MIN_LENGTH,
-            min_gap_length: DEFAULT_FORM_LINES_MIN_GAP_LENGTH,
+            steepness: DEFAULT_FORM_LINES_STEEPNESS,
             additional_tail_length: DEFAULT_FORM_LINES_ADDITIONAL_TAIL_LENGTH,
+            min_gap_length: DEFAULT_FORM_LINES_MIN_GAP_LENGTH,
+            dash_length: DEFAULT_FORM_LINES_DASH_LENGTH,
+            gap_length: DEFAULT_FORM_LINES_GAP_LENGTH,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ContoursConfig {
+    #[serde(default = "default_contours_interval")]
+    pub interval: f64,
+    #[serde(default = "default_contours_index_multiple")]
+    pub index_multiple: u32,
+    #[serde(default = "default_contours_smoothing")]
+    pub smoothing: f64,
+    #[serde(default = "default_contours_curviness")]
+    pub curviness: f64,
+}
+
+impl Default for ContoursConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_CONTOURS_INTERVAL,
+            index_multiple: DEFAULT_CONTOURS_INDEX_MULTIPLE,
+            smoothing: DEFAULT_CONTOURS_SMOOTHING,
+            curviness: DEFAULT_CONTOURS_CURVINESS,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LidarConfig {
+    #[serde(default = "default_lidar_ground_classes")]
+    pub ground_classes: Vec<u8>,
+    #[serde(default = "default_lidar_sampling_cell_size")]
+    pub sampling_cell_size: f64,
+    #[serde(default = "default_lidar_height_bands")]
+    pub height_bands: Vec<HeightBand>,
+    /// The band whose density decides if an area is open land
+    #[serde(default = "default_lidar_yellow_band")]
+    pub yellow_band: String,
+    /// The band whose density decides how green an area is
+    #[serde(default = "default_lidar_green_band")]
+    pub green_band: String,
+    #[serde(default)]
+    pub density_normalization: DensityNormalization,
+    #[serde(default)]
+    pub ground_classification: GroundClassificationConfig,
+}
+
+impl Default for LidarConfig {
+    fn default() -> Self {
+        Self {
+            ground_classes: default_lidar_ground_classes(),
+            sampling_cell_size: DEFAULT_LIDAR_SAMPLING_CELL_SIZE,
+            height_bands: default_lidar_height_bands(),
+            yellow_band: default_lidar_yellow_band(),
+            green_band: default_lidar_green_band(),
+            density_normalization: DensityNormalization::default(),
+            ground_classification: GroundClassificationConfig::default(),
         }
     }
 }
 
-pub fn get_config() -> Config {
-    let raw_config = fs::read_to_string("./config.json").unwrap_or("{}".to_owned());
-    return serde_json::from_str(&raw_config).unwrap();
+/// A range of heights above ground, rasterized as a `<name>.tif` point count raster.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HeightBand {
+    pub name: String,
+    pub min_height: f64,
+    pub max_height: f64,
 }
 
-pub fn generate_default_config() {
+impl HeightBand {
+    pub fn contains(&self, height_above_ground: f64) -> bool {
+        height_above_ground > self.min_height && height_above_ground <= self.max_height
+    }
+}
+
+/// What the height band rasters contain, and so what the vegetation thresholds are compared to.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DensityNormalization {
+    /// The number of sampled points of the band in every cell
+    #[default]
+    None,
+    /// The ratio of the returns of the band to all the returns of the cell
+    TotalReturns,
+    /// The ratio of the returns of the band to the returns at or below its maximum height,
+    /// so a dense canopy does not hide the undergrowth
+    ReturnsBelow,
+}
+
+/// When to classify ground points with a morphological filter instead of using the
+/// classification of the LiDAR file.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GroundClassificationMode {
+    /// Only when the file has (almost) no point in the ground classes
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GroundClassificationConfig {
+    #[serde(default)]
+    pub mode: GroundClassificationMode,
+    #[serde(default = "default_ground_classification_min_ground_ratio")]
+    pub min_ground_ratio: f64,
+    #[serde(default = "default_ground_classification_cell_size")]
+    pub cell_size: f64,
+    #[serde(default = "default_ground_classification_max_window_size")]
+    pub max_window_size: f64,
+    #[serde(default = "default_ground_classification_slope")]
+    pub slope: f64,
+    #[serde(default = "default_ground_classification_elevation_threshold")]
+    pub elevation_threshold: f64,
+}
+
+impl Default for GroundClassificationConfig {
+    fn default() -> Self {
+        Self {
+            mode: GroundClassificationMode::default(),
+            min_ground_ratio: DEFAULT_GROUND_CLASSIFICATION_MIN_GROUND_RATIO,
+            cell_size: DEFAULT_GROUND_CLASSIFICATION_CELL_SIZE,
+            max_window_size: DEFAULT_GROUND_CLASSIFICATION_MAX_WINDOW_SIZE,
+            slope: DEFAULT_GROUND_CLASSIFICATION_SLOPE,
+            elevation_threshold: DEFAULT_GROUND_CLASSIFICATION_ELEVATION_THRESHOLD,
+        }
+    }
+}
+
+/// How the holes of the DEM are interpolated.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FillMethod {
+    /// Weighted by the inverse squared distance to the nearest valid cells around the hole
+    #[default]
+    InverseDistance,
+    /// The smoothest surface joining the edges of the hole, without the bumps the inverse
+    /// distance weighting leaves under bridges and along buildings
+    Laplacian,
+}
+
+/// How the holes of the DEM, where no ground point was found, are filled before tracing the
+/// contours and cliffs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DemConfig {
+    #[serde(default)]
+    pub fill_method: FillMethod,
+    /// The maximum distance to a valid cell an interpolated cell can be, in meters
+    #[serde(default = "default_dem_fill_max_distance")]
+    pub fill_max_distance: f64,
+}
+
+impl Default for DemConfig {
+    fn default() -> Self {
+        Self {
+            fill_method: FillMethod::default(),
+            fill_max_distance: DEFAULT_DEM_FILL_MAX_DISTANCE,
+        }
+    }
+}
+
+/// How the slope and the other terrain derivatives used to detect landforms are computed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TerrainConfig {
+    /// The radius in meters of the mean filter applied to the DEM first, 0 to disable it
+    #[serde(default = "default_terrain_smoothing_radius")]
+    pub smoothing_radius: f64,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            smoothing_radius: DEFAULT_TERRAIN_SMOOTHING_RADIUS,
+        }
+    }
+}
+
+/// Where low vegetation slowing running without hiding the view is drawn with green stripes.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UndergrowthConfig {
+    /// The low height band whose density decides if an area has undergrowth, none to disable it
+    #[serde(default = "default_undergrowth_band")]
+    pub band: Option<String>,
+    #[serde(default = "default_undergrowth_threshold_1")]
+    pub threshold_1: f64,
+    #[serde(default = "default_undergrowth_threshold_2")]
+    pub threshold_2: f64,
+    /// The density of the yellow band above which the canopy is too closed to tell the undergrowth
+    #[serde(default = "default_undergrowth_max_canopy_density")]
+    pub max_canopy_density: f64,
+}
+
+impl Default for UndergrowthConfig {
+    fn default() -> Self {
+        Self {
+            band: default_undergrowth_band(),
+            threshold_1: DEFAULT_UNDERGROWTH_THRESHOLD_1,
+            threshold_2: DEFAULT_UNDERGROWTH_THRESHOLD_2,
+            max_canopy_density: DEFAULT_UNDERGROWTH_MAX_CANOPY_DENSITY,
+        }
+    }
+}
+
+/// Which of the tree tops found in the canopy height model are drawn as prominent large trees.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TreesConfig {
+    /// The minimum height above ground of a tree top, in meters
+    #[serde(default = "default_trees_min_height")]
+    pub min_height: f64,
+    /// The minimum distance to any other tree top, in meters
+    #[serde(default = "default_trees_isolation_distance")]
+    pub isolation_distance: f64,
+}
+
+impl Default for TreesConfig {
+    fn default() -> Self {
+        Self {
+            min_height: DEFAULT_TREES_MIN_HEIGHT,
+            isolation_distance: DEFAULT_TREES_ISOLATION_DISTANCE,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(io::Error),
+    Parse(serde_json::Error),
+    Invalid(Vec<ConfigValidationError>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(error) => write!(f, "Could not read config file: {}", error),
+            ConfigError::Parse(error) => write!(f, "Could not parse config file: {}", error),
+            ConfigError::Invalid(errors) => {
+                write!(f, "Invalid config:")?;
+
+                for error in errors {
+                    write!(f, "\n  - {}", error)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigValidationError {
+    pub key: String,
+    pub value: String,
+    pub constraint: String,
+}
+
+impl std::error::Error for ConfigError {}
+
+impl fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\" is {}, it {}",
+            self.key, self.value, self.constraint
+        )
+    }
+}
+
+/// Reads, parses and validates the config file in the current directory.
+/// Unknown keys are printed as warnings, a missing file falls back to the default config.
+pub fn get_config() -> Result<Config, ConfigError> {
+    let (config, unknown_keys) = read_config(Path::new(CONFIG_FILE_PATH))?;
+
+    for key in unknown_keys {
+        eprintln!("Warning: unknown config key \"{}\" will be ignored", key);
+    }
+
+    Ok(config)
+}
+
+/// Checks the config file without running the pipeline. Returns false if it is invalid.
+pub fn check_config() -> bool {
+    let config_path = Path::new(CONFIG_FILE_PATH);
+
+    if !config_path.exists() {
+        println!("No config file found, the default config will be used");
+        return true;
+    }
+
+    match read_config(config_path) {
+        Ok((_, unknown_keys)) => {
+            for key in &unknown_keys {
+                println!("Warning: unknown config key \"{}\" will be ignored", key);
+            }
+
+            println!("Config is valid");
+            true
+        }
+        Err(error) => {
+            println!("{}", error);
+            false
+        }
+    }
+}
+
+pub fn read_config(path: &Path) -> Result<(Config, Vec<String>), ConfigError> {
+    let raw_config = match fs::read_to_string(path) {
+        Ok(raw_config) => raw_config,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => "{}".to_owned(),
+        Err(error) => return Err(ConfigError::Read(error)),
+    };
+
+    // Parsing the raw text so type errors point to their line and column, the value being only
+    // used to find unknown keys
+    let config: Config = serde_json::from_str(&raw_config).map_err(ConfigError::Parse)?;
+    let raw_value: Value = serde_json::from_str(&raw_config).map_err(ConfigError::Parse)?;
+
+    let default_config: Config = serde_json::from_str("{}").unwrap();
+    let reference_value = serde_json::to_value(&default_config).unwrap();
+    let mut unknown_keys: Vec<String> = vec![];
+    collect_unknown_keys(&raw_value, &reference_value, "", &mut unknown_keys);
+
+    let errors = validate_config(&config);
+
+    if !errors.is_empty() {
+        return Err(ConfigError::Invalid(errors));
+    }
+
+    Ok((config, unknown_keys))
+}
+
+fn collect_unknown_keys(
+    value: &Value,
+    reference: &Value,
+    prefix: &str,
+    unknown_keys: &mut Vec<String>,
+) {
+    let (Value::Object(object), Value::Object(reference_object)) = (value, reference) else {
+        return;
+    };
+
+    for (key, child_value) in object {
+        let path = format!("{}{}", prefix, key);
+
+        match reference_object.get(key) {
+            Some(child_reference) => collect_unknown_keys(
+                child_value,
+                child_reference,
+                &format!("{}.", path),
+                unknown_keys,
+            ),
+            None => unknown_keys.push(path),
+        }
+    }
+}
+
+pub fn validate_config(config: &Config) -> Vec<ConfigValidationError> {
+    let mut errors: Vec<ConfigValidationError> = vec![];
+
+    check_non_negative(&mut errors, "yellow_threshold", config.yellow_threshold);
+    check_non_negative(&mut errors, "green_threshold_1", config.green_threshold_1);
+    check_greater_than(
+        &mut errors,
+        ("green_threshold_2", config.green_threshold_2),
+        ("green_threshold_1", config.green_threshold_1),
+    );
+    check_greater_than(
+        &mut errors,
+        ("green_threshold_3", config.green_threshold_3),
+        ("green_threshold_2", config.green_threshold_2),
+    );
+    check_non_negative(
+        &mut errors,
+        "cliff_threshold_1",
+        config.cliff_threshold_1 as f64,
+    );
+    check_greater_than(
+        &mut errors,
+        ("cliff_threshold_2", config.cliff_threshold_2 as f64),
+        ("cliff_threshold_1", config.cliff_threshold_1 as f64),
+    );
+    check_positive(&mut errors, "dpi_resolution", config.dpi_resolution as f64);
+
+    if let Some(epsg_code) = config.epsg_code {
+        check_positive(&mut errors, "epsg_code", epsg_code as f64);
+    }
+
+    check_non_negative(
+        &mut errors,
+        "form_lines.steepness",
+        config.form_lines.steepness,
+    );
+    check_non_negative(
+        &mut errors,
+        "form_lines.additional_tail_length",
+        config.form_lines.additional_tail_length,
+    );
+    check_positive(
+        &mut errors,
+        "form_lines.dash_length",
+        config.form_lines.dash_length,
+    );
+    check_non_negative(
+        &mut errors,
+        "form_lines.gap_length",
+        config.form_lines.gap_length,
+    );
+
+    check_positive(&mut errors, "contours.interval", config.contours.interval);
+    check_positive(
+        &mut errors,
+        "contours.index_multiple",
+        config.contours.index_multiple as f64,
+    );
+    check_non_negative(&mut errors, "contours.smoothing", config.contours.smoothing);
+    check_non_negative(&mut errors, "contours.curviness", config.contours.curviness);
+
+    if config.lidar.ground_classes.is_empty() {
+        errors.push(ConfigValidationError {
+            key: "lidar.ground_classes".to_owned(),
+            value: "empty".to_owned(),
+            constraint: "must contain at least one classification code".to_owned(),
+        });
+    }
+
+    check_non_negative(
+        &mut errors,
+        "lidar.sampling_cell_size",
+        config.lidar.sampling_cell_size,
+    );
+
+    for (index, band) in config.lidar.height_bands.iter().enumerate() {
+        let key = format!("lidar.height_bands[{}]", index);
+        check_band_name(&mut errors, &format!("{}.name", key), &band.name);
+
+        if config.lidar.height_bands[..index]
+            .iter()
+            .any(|other_band| other_band.name == band.name)
+        {
+            errors.push(ConfigValidationError {
+                key: format!("{}.name", key),
+                value: format!("\"{}\"", band.name),
+                constraint: "must be unique".to_owned(),
+            });
+        }
+
+        check_non_negative(&mut errors, &format!("{}.min_height", key), band.min_height);
+        check_greater_than(
+            &mut errors,
+            (&format!("{}.max_height", key), band.max_height),
+            (&format!("{}.min_height", key), band.min_height),
+        );
+    }
+
+    check_band_exists(
+        &mut errors,
+        "lidar.yellow_band",
+        &config.lidar.yellow_band,
+        config,
+    );
+    check_band_exists(
+        &mut errors,
+        "lidar.green_band",
+        &config.lidar.green_band,
+        config,
+    );
+
+    check_positive(
+        &mut errors,
+        "dem.fill_max_distance",
+        config.dem.fill_max_distance,
+    );
+
+    check_non_negative(
+        &mut errors,
+        "terrain.smoothing_radius",
+        config.terrain.smoothing_radius,
+    );
+
+    if let Some(band) = &config.undergrowth.band {
+        check_band_exists(&mut errors, "undergrowth.band", band, config);
+    }
+
+    check_non_negative(
+        &mut errors,
+        "undergrowth.threshold_1",
+        config.undergrowth.threshold_1,
+    );
+    check_greater_than(
+        &mut errors,
+        ("undergrowth.threshold_2", config.undergrowth.threshold_2),
+        ("undergrowth.threshold_1", config.undergrowth.threshold_1),
+    );
+    check_non_negative(
+        &mut errors,
+        "undergrowth.max_canopy_density",
+        config.undergrowth.max_canopy_density,
+    );
+
+    check_positive(&mut errors, "trees.min_height", config.trees.min_height);
+    check_non_negative(
+        &mut errors,
+        "trees.isolation_distance",
+        config.trees.isolation_distance,
+    );
+
+    // Normalized densities are ratios, a threshold above 1 would never be reached
+    if config.lidar.density_normalization != DensityNormalization::None {
+        for (key, value) in [
+            ("yellow_threshold", config.yellow_threshold),
+            ("green_threshold_1", config.green_threshold_1),
+            ("green_threshold_2", config.green_threshold_2),
+            ("green_threshold_3", config.green_threshold_3),
+            ("undergrowth.threshold_1", config.undergrowth.threshold_1),
+            ("undergrowth.threshold_2", config.undergrowth.threshold_2),
+            (
+                "undergrowth.max_canopy_density",
+                config.undergrowth.max_canopy_density,
+            ),
+        ] {
+            check_ratio(&mut errors, key, value);
+        }
+    }
+
+    errors
+}
+
+fn check_band_name(errors: &mut Vec<ConfigValidationError>, key: &str, name: &str) {
+    let is_valid_file_name = !name.is_empty()
+        && name.chars().all(|character| {
+            character.is_ascii_alphanumeric() || character == '-' || character == '_'
+        });
+
+    if !is_valid_file_name {
+        errors.push(ConfigValidationError {
+            key: key.to_owned(),
+            value: format!("\"{}\"", name),
+            constraint: "must only contain letters, digits, '-' and '_'".to_owned(),
+        });
+    } else if RESERVED_BAND_NAMES.contains(&name) {
+        errors.push(ConfigValidationError {
+            key: key.to_owned(),
+            value: format!("\"{}\"", name),
+            constraint: format!("must not be one of {:?}", RESERVED_BAND_NAMES),
+        });
+    }
+}
+
+fn check_band_exists(
+    errors: &mut Vec<ConfigValidationError>,
+    key: &str,
+    name: &str,
+    config: &Config,
+) {
+    if !config
+        .lidar
+        .height_bands
+        .iter()
+        .any(|band| band.name == name)
+    {
+        errors.push(ConfigValidationError {
+            key: key.to_owned(),
+            value: format!("\"{}\"", name),
+            constraint: "must be the name of one of \"lidar.height_bands\"".to_owned(),
+        });
+    }
+}
+
+fn check_positive(errors: &mut Vec<ConfigValidationError>, key: &str, value: f64) {
+    if value.is_nan() || value <= 0.0 {
+        errors.push(ConfigValidationError {
+            key: key.to_owned(),
+            value: value.to_string(),
+            constraint: "must be strictly positive".to_owned(),
+        });
+    }
+}
+
+fn check_non_negative(errors: &mut Vec<ConfigValidationError>, key: &str, value: f64) {
+    if value.is_nan() || value < 0.0 {
+        errors.push(ConfigValidationError {
+            key: key.to_owned(),
+            value: value.to_string(),
+            constraint: "must be positive or zero".to_owned(),
+        });
+    }
+}
+
+fn check_ratio(errors: &mut Vec<ConfigValidationError>, key: &str, value: f64) {
+    if value > 1.0 {
+        errors.push(ConfigValidationError {
+            key: key.to_owned(),
+            value: value.to_string(),
+            constraint: "must be at most 1 when \"lidar.density_normalization\" is enabled"
+                .to_owned(),
+        });
+    }
+}
+
+fn check_greater_than(
+    errors: &mut Vec<ConfigValidationError>,
+    (key, value): (&str, f64),
+    (lower_key, lower_value): (&str, f64),
+) {
+    if value.is_nan() || value <= lower_value {
+        errors.push(ConfigValidationError {
+            key: key.to_owned(),
+            value: value.to_string(),
+            constraint: format!("must be greater than \"{}\" ({})", lower_key, lower_value),
+        });
+    }
+}
+
+pub fn generate_default_config() -> Result<(), Error> {
     let default_config: Config = serde_json::from_str("{}").unwrap();
     let json_string = serde_json::to_string_pretty(&default_config).unwrap();
-    let mut file = File::create("config.json").unwrap();
-    file.write_all(json_string.as_bytes()).unwrap();
+    let config_path = Path::new(CONFIG_FILE_PATH);
+    let mut file = File::create(config_path).map_err(file_error(config_path))?;
+    file.write_all(json_string.as_bytes())
+        .map_err(file_error(config_path))?;
+    Ok(())
 }
 
 fn default_yellow_threshold() -> f64 {
@@ -148,26 +809,135 @@ fn default_dpi_resolution() -> f32 {
     DEFAULT_DPI_RESOLUTION
 }
 
-fn default_form_lines_threshold() -> f64 {
-    DEFAULT_FORM_LINES_THRESHOLD
+fn default_input_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_INPUT_DIR)
 }
 
-fn default_form_lines_min_distance_to_contour() -> f64 {
-    DEFAULT_FORM_LINES_MIN_DISTANCE_TO_CONTOUR
+fn default_output_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_OUTPUT_DIR)
 }
 
-fn default_form_lines_max_distance_to_contour() -> f64 {
-    DEFAULT_FORM_LINES_MAX_DISTANCE_TO_CONTOUR
+fn default_form_lines_steepness() -> f64 {
+    DEFAULT_FORM_LINES_STEEPNESS
 }
 
-fn default_form_lines_min_length() -> f64 {
-    DEFAULT_FORM_LINES_MIN_LENGTH
+fn default_form_lines_additional_tail_length() -> f64 {
+    DEFAULT_FORM_LINES_ADDITIONAL_TAIL_LENGTH
 }
 
-fn default_form_lines_min_gap_length() -> f64 {
+fn default_form_lines_min_gap_length() -> u32 {
     DEFAULT_FORM_LINES_MIN_GAP_LENGTH
 }
 
-fn default_form_lines_additional_tail_length() -> f64 {
-    DEFAULT_FORM_LINES_ADDITIONAL_TAIL_LENGTH
+fn default_form_lines_dash_length() -> f64 {
+    DEFAULT_FORM_LINES_DASH_LENGTH
+}
+
+fn default_form_lines_gap_length() -> f64 {
+    DEFAULT_FORM_LINES_GAP_LENGTH
+}
+
+fn default_contours_interval() -> f64 {
+    DEFAULT_CONTOURS_INTERVAL
+}
+
+fn default_contours_index_multiple() -> u32 {
+    DEFAULT_CONTOURS_INDEX_MULTIPLE
+}
+
+fn default_contours_smoothing() -> f64 {
+    DEFAULT_CONTOURS_SMOOTHING
+}
+
+fn default_contours_curviness() -> f64 {
+    DEFAULT_CONTOURS_CURVINESS
+}
+
+fn default_lidar_ground_classes() -> Vec<u8> {
+    DEFAULT_LIDAR_GROUND_CLASSES.to_vec()
+}
+
+fn default_lidar_sampling_cell_size() -> f64 {
+    DEFAULT_LIDAR_SAMPLING_CELL_SIZE
+}
+
+// Update the docs when modifying
+fn default_lidar_height_bands() -> Vec<HeightBand> {
+    vec![
+        HeightBand {
+            name: "undergrowth".to_owned(),
+            min_height: 0.3,
+            max_height: 1.5,
+        },
+        HeightBand {
+            name: "medium-vegetation".to_owned(),
+            min_height: 0.3,
+            max_height: 4.,
+        },
+        HeightBand {
+            name: "high-vegetation".to_owned(),
+            min_height: 4.,
+            max_height: 30.,
+        },
+    ]
+}
+
+fn default_lidar_yellow_band() -> String {
+    DEFAULT_LIDAR_YELLOW_BAND.to_owned()
+}
+
+fn default_lidar_green_band() -> String {
+    DEFAULT_LIDAR_GREEN_BAND.to_owned()
+}
+
+fn default_ground_classification_min_ground_ratio() -> f64 {
+    DEFAULT_GROUND_CLASSIFICATION_MIN_GROUND_RATIO
+}
+
+fn default_ground_classification_cell_size() -> f64 {
+    DEFAULT_GROUND_CLASSIFICATION_CELL_SIZE
+}
+
+fn default_ground_classification_max_window_size() -> f64 {
+    DEFAULT_GROUND_CLASSIFICATION_MAX_WINDOW_SIZE
+}
+
+fn default_ground_classification_slope() -> f64 {
+    DEFAULT_GROUND_CLASSIFICATION_SLOPE
+}
+
+fn default_ground_classification_elevation_threshold() -> f64 {
+    DEFAULT_GROUND_CLASSIFICATION_ELEVATION_THRESHOLD
+}
+
+fn default_dem_fill_max_distance() -> f64 {
+    DEFAULT_DEM_FILL_MAX_DISTANCE
+}
+
+fn default_terrain_smoothing_radius() -> f64 {
+    DEFAULT_TERRAIN_SMOOTHING_RADIUS
+}
+
+fn default_undergrowth_band() -> Option<String> {
+    Some(DEFAULT_UNDERGROWTH_BAND.to_owned())
+}
+
+fn default_undergrowth_threshold_1() -> f64 {
+    DEFAULT_UNDERGROWTH_THRESHOLD_1
+}
+
+fn default_undergrowth_threshold_2() -> f64 {
+    DEFAULT_UNDERGROWTH_THRESHOLD_2
+}
+
+fn default_undergrowth_max_canopy_density() -> f64 {
+    DEFAULT_UNDERGROWTH_MAX_CANOPY_DENSITY
+}
+
+fn default_trees_min_height() -> f64 {
+    DEFAULT_TREES_MIN_HEIGHT
+}
+
+fn default_trees_isolation_distance() -> f64 {
+    DEFAULT_TREES_ISOLATION_DISTANCE
 }
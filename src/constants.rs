@@ -19,6 +19,7 @@ pub const VECTOR_BLUE: (u8, u8, u8) = (0, 255, 255);
 pub const VECTOR_BLACK: (u8, u8, u8) = (0, 0, 0);
 pub const VECTOR_BUILDING_GRAY: (u8, u8, u8) = (89, 89, 89);
 pub const VECTOR_PAVED_AREA_BROWN: (u8, u8, u8) = (232, 174, 128);
+pub const VECTOR_GREEN: (u8, u8, u8) = (61, 255, 23);
 
 pub const CLIFF_THICKNESS_1: f32 = 0.25;
 pub const CLIFF_THICKNESS_2: f32 = 0.35;
@@ -38,3 +39,7 @@ pub const FOOTPATH_DASH_INTERVAL_LENGTH: f32 = 0.25;
 pub const CROSSABLE_WATERCOURSE_WIDTH: f32 = 0.3;
 pub const MARSH_LINE_WIDTH: f32 = 0.1;
 pub const MARSH_LINE_SPACING: f32 = 0.3;
+pub const UNDERGROWTH_LINE_WIDTH: f32 = 0.35;
+pub const UNDERGROWTH_LINE_SPACING: f32 = 0.25;
+pub const PROMINENT_LARGE_TREE_DIAMETER: f32 = 1.2;
+pub const PROMINENT_LARGE_TREE_OUTLINE_WIDTH: f32 = 0.2;
@@ -1,5 +1,6 @@
 use crate::{
-    config::Config, pullautin_contours_render::pullautin_cull_formlines_render_contours,
+    config::Config, error::Error,
+    pullautin_contours_render::pullautin_cull_formlines_render_contours,
     pullautin_smooth_contours::pullautin_smooth_contours, tile::Tile,
 };
 
@@ -8,15 +9,15 @@ pub fn generate_contours_with_pullautin_algorithme(
     image_width: u32,
     image_height: u32,
     config: &Config,
-) {
-    let (avg_alt, smoothed_contours) = pullautin_smooth_contours(&tile);
+) -> Result<(), Error> {
+    let (avg_alt, smoothed_contours) = pullautin_smooth_contours(tile, config)?;
 
     pullautin_cull_formlines_render_contours(
-        &tile,
+        tile,
         image_width,
         image_height,
-        &config,
+        config,
         avg_alt,
         smoothed_contours,
-    );
+    )
 }
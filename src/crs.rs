@@ -0,0 +1,107 @@
+use crate::error::Error;
+use las::{Header, Vlr};
+use std::path::Path;
+
+const PROJECTION_USER_ID: &str = "LASF_Projection";
+const GEO_KEY_DIRECTORY_RECORD_ID: u16 = 34735;
+const WKT_RECORD_ID: u16 = 2112;
+const PROJECTED_CRS_GEO_KEY: u16 = 3072;
+const USER_DEFINED_GEO_KEY_VALUE: u16 = 32767;
+
+/// The EPSG code of a LiDAR file CRS: the one of the config if set, otherwise the one declared in
+/// the file header.
+pub fn get_epsg_code(
+    path: &Path,
+    header: &Header,
+    config_epsg_code: Option<u16>,
+) -> Result<u16, Error> {
+    config_epsg_code
+        .or_else(|| read_header_epsg_code(header))
+        .ok_or_else(|| Error::MissingCrs(path.to_path_buf()))
+}
+
+/// Reads the EPSG code of the projected CRS from the WKT or GeoTIFF keys VLR of a LAS header.
+pub fn read_header_epsg_code(header: &Header) -> Option<u16> {
+    let projection_vlrs: Vec<&Vlr> = header
+        .vlrs()
+        .iter()
+        .chain(header.evlrs())
+        .filter(|vlr| vlr.user_id == PROJECTION_USER_ID)
+        .collect();
+
+    // LAS 1.4 files with a WKT VLR may also keep outdated GeoTIFF keys, the WKT takes precedence
+    let wkt_epsg_code = projection_vlrs
+        .iter()
+        .filter(|vlr| vlr.record_id == WKT_RECORD_ID)
+        .find_map(|vlr| parse_wkt_epsg_code(&String::from_utf8_lossy(&vlr.data)));
+
+    wkt_epsg_code.or_else(|| {
+        projection_vlrs
+            .iter()
+            .filter(|vlr| vlr.record_id == GEO_KEY_DIRECTORY_RECORD_ID)
+            .find_map(|vlr| parse_geo_keys_epsg_code(&vlr.data))
+    })
+}
+
+/// Finds the authority code of the projected CRS of a WKT 1 or WKT 2 string, ignoring the
+/// authorities of its components (datum, units...) and of an enclosing compound CRS.
+fn parse_wkt_epsg_code(wkt: &str) -> Option<u16> {
+    let start = ["PROJCRS[", "PROJCS["]
+        .iter()
+        .find_map(|keyword| wkt.find(keyword).map(|index| index + keyword.len()))?;
+
+    let projected_crs = &wkt[start..];
+    let mut depth = 0;
+    let mut is_quoted = false;
+    let mut authority = None;
+
+    for (index, character) in projected_crs.char_indices() {
+        match character {
+            '"' => is_quoted = !is_quoted,
+            _ if is_quoted => {}
+            '[' | '(' => depth += 1,
+            ']' | ')' if depth == 0 => break,
+            ']' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                let element = projected_crs[index + 1..].trim_start();
+
+                for keyword in ["AUTHORITY[", "ID["] {
+                    if let Some(arguments) = element.strip_prefix(keyword) {
+                        authority = Some(arguments);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let arguments = authority?;
+    let arguments = &arguments[..arguments.find(']')?];
+    let mut values = arguments
+        .split(',')
+        .map(|value| value.trim().trim_matches('"'));
+
+    if !values.next()?.eq_ignore_ascii_case("EPSG") {
+        return None;
+    }
+
+    values.next()?.parse().ok()
+}
+
+/// Finds the projected CRS key in a GeoTIFF key directory: a header of 4 shorts, the last one being
+/// the number of keys, followed by the keys (id, location, count, value).
+fn parse_geo_keys_epsg_code(data: &[u8]) -> Option<u16> {
+    let shorts: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect();
+
+    let number_of_keys = *shorts.get(3)? as usize;
+
+    shorts
+        .get(4..4 + 4 * number_of_keys)?
+        .chunks_exact(4)
+        .find(|key| key[0] == PROJECTED_CRS_GEO_KEY && key[1] == 0)
+        .map(|key| key[3])
+        .filter(|value| *value != USER_DEFINED_GEO_KEY_VALUE)
+}
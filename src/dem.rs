@@ -1,108 +1,51 @@
 use crate::{
-    buffer::create_tif_with_buffer,
+    buffer::mosaic_with_buffer,
+    config::Config,
     constants::BUFFER,
+    error::Error,
+    fill_holes::fill_holes,
     tile::{NeighborTiles, Tile},
 };
 use std::{
-    fs::create_dir_all,
     io::{stdout, Write},
-    process::{Command, ExitStatus},
     time::Instant,
 };
 
-pub fn create_dem_with_buffer_and_slopes_tiff(tile: &Tile, neighbor_tiles: &NeighborTiles) {
+pub fn create_dem_with_buffer(
+    tile: &Tile,
+    neighbor_tiles: &NeighborTiles,
+    config: &Config,
+) -> Result<(), Error> {
     print!("Generating dem with buffer");
     let _ = stdout().flush();
     let start = Instant::now();
 
-    let dem_with_buffer_path = tile.dir_path.join("dem-with-buffer.tif");
-    create_tif_with_buffer(tile, neighbor_tiles, BUFFER as i64, "dem");
-
-    // Filling holes
-    let gdal_fillnodata_output = Command::new("gdal_fillnodata")
-        .arg(&dem_with_buffer_path.to_str().unwrap())
-        .arg(&dem_with_buffer_path.to_str().unwrap())
-        .output()
-        .expect("failed to execute gdal_contour command");
-
-    if !ExitStatus::success(&gdal_fillnodata_output.status) {
-        println!(
-            "{}",
-            String::from_utf8(gdal_fillnodata_output.stderr).unwrap()
-        );
-    }
-
-    let dem_low_resolution_with_buffer_path =
-        tile.dir_path.join("dem-low-resolution-with-buffer.tif");
-
-    create_tif_with_buffer(tile, neighbor_tiles, BUFFER as i64, "dem-low-resolution");
-
-    // Filling holes
-    let gdal_fillnodata_output = Command::new("gdal_fillnodata")
-        .arg(&dem_low_resolution_with_buffer_path.to_str().unwrap())
-        .arg(&dem_low_resolution_with_buffer_path.to_str().unwrap())
-        .output()
-        .expect("failed to execute gdal_contour command");
-
-    if !ExitStatus::success(&gdal_fillnodata_output.status) {
-        println!(
-            "{}",
-            String::from_utf8(gdal_fillnodata_output.stderr).unwrap()
-        );
-    }
+    create_filled_dem_with_buffer(tile, neighbor_tiles, "dem", config)?;
+    create_filled_dem_with_buffer(tile, neighbor_tiles, "dem-low-resolution", config)?;
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
 
-    print!("Generating countours shapefiles");
-    let _ = stdout().flush();
-    let start = Instant::now();
-
-    let contours_raw_dir = tile.dir_path.join("contours-raw");
-    create_dir_all(&contours_raw_dir).expect("Could not create contours-raw dir");
-    let contours_raw_path = contours_raw_dir.join("contours-raw.shp");
-
-    let gdal_contours_output = Command::new("gdal_contour")
-        .args([
-            "-a",
-            "elev",
-            &dem_low_resolution_with_buffer_path.to_str().unwrap(),
-            &contours_raw_path.to_str().unwrap(),
-            "-i",
-            "2.5",
-        ])
-        .output()
-        .expect("failed to execute gdal_contour command");
-
-    if !ExitStatus::success(&gdal_contours_output.status) {
-        println!(
-            "{}",
-            String::from_utf8(gdal_contours_output.stderr).unwrap()
-        );
-    }
-
-    let duration = start.elapsed();
-    println!(" -> Done in {:.1?}", duration);
-
-    print!("Generating slopes tif image");
-    let _ = stdout().flush();
-    let start = Instant::now();
-
-    let slopes_path = tile.dir_path.join("slopes.tif");
-
-    let gdaldem_output = Command::new("gdaldem")
-        .args([
-            "slope",
-            &dem_with_buffer_path.to_str().unwrap(),
-            &slopes_path.to_str().unwrap(),
-        ])
-        .output()
-        .expect("failed to execute gdaldem command");
-
-    if !ExitStatus::success(&gdaldem_output.status) {
-        println!("{}", String::from_utf8(gdaldem_output.stderr).unwrap());
-    }
+    Ok(())
+}
 
-    let duration = start.elapsed();
-    println!(" -> Done in {:.1?}", duration);
+/// Mosaics a DEM with the ones of the neighbors and fills its holes, writing the result and the
+/// mask of its interpolated cells to the tile directory.
+fn create_filled_dem_with_buffer(
+    tile: &Tile,
+    neighbor_tiles: &NeighborTiles,
+    dem_name: &str,
+    config: &Config,
+) -> Result<(), Error> {
+    let dem_path = tile.dir_path.join(format!("{}-with-buffer.tif", dem_name));
+    let interpolated_mask_path = tile
+        .dir_path
+        .join(format!("{}-with-buffer-interpolated.tif", dem_name));
+
+    let dem = mosaic_with_buffer(tile, neighbor_tiles, BUFFER as i64, dem_name)?;
+    let (filled_dem, interpolated_mask) =
+        fill_holes(&dem, config.dem.fill_method, config.dem.fill_max_distance);
+
+    filled_dem.write_geotiff(&dem_path, tile.epsg_code)?;
+    interpolated_mask.write_geotiff(&interpolated_mask_path, tile.epsg_code)
 }
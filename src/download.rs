@@ -6,80 +6,97 @@ use std::{
     time::Instant,
 };
 
-use crate::{constants::BUFFER, tile::TileWithNeighbors};
-
-pub fn download_osm_files_for_all_tiles_if_needed(tiles: &Vec<TileWithNeighbors>) {
-    for tile in tiles {
-        download_osm_file_if_needed(
-            tile.tile.min_x,
-            tile.tile.min_y,
-            tile.tile.max_x,
-            tile.tile.max_y,
-        );
-    }
-}
+use crate::{
+    command::run_command,
+    constants::BUFFER,
+    error::{file_error, Error},
+};
 
-pub fn download_osm_file_if_needed(min_x: i64, min_y: i64, max_x: i64, max_y: i64) {
-    let osm_file_path = Path::new("in").join(format!("{:0>7}_{:0>7}.osm", min_x, max_y));
+pub fn download_osm_file_if_needed(
+    min_x: i64,
+    min_y: i64,
+    max_x: i64,
+    max_y: i64,
+    epsg_code: u16,
+    input_dir: &Path,
+) -> Result<(), Error> {
+    let osm_file_path = input_dir.join(format!("{:0>7}_{:0>7}.osm", min_x, max_y));
 
     if osm_file_path.exists() {
         println!("Osm file already downloaded");
-        return;
+        return Ok(());
     }
 
     print!("Downloading osm file");
     let _ = stdout().flush();
     let start = Instant::now();
 
-    let (min_lon, min_lat) = convert_coords_from_lambert_93_to_gps(
+    let (min_lon, min_lat) = convert_coords_to_gps(
         (min_x - BUFFER as i64) as f64,
         (min_y - BUFFER as i64) as f64,
-    );
+        epsg_code,
+    )?;
 
-    let (max_lon, max_lat) = convert_coords_from_lambert_93_to_gps(
+    let (max_lon, max_lat) = convert_coords_to_gps(
         (max_x + BUFFER as i64) as f64,
         (max_y + BUFFER as i64) as f64,
-    );
+        epsg_code,
+    )?;
 
     let mut response = reqwest::blocking::get(&format!(
         "https://www.openstreetmap.org/api/0.6/map?bbox={}%2C{}%2C{}%2C{}",
         min_lon, min_lat, max_lon, max_lat,
-    ))
-    .expect("Could not download osm file.");
+    ))?
+    .error_for_status()?;
 
-    let mut file = File::create(&osm_file_path).expect("Could not create file for osm download.");
-    copy(&mut response, &mut file).expect("Could not copy file content.");
+    let mut file = File::create(&osm_file_path).map_err(file_error(&osm_file_path))?;
+    copy(&mut response, &mut file).map_err(file_error(&osm_file_path))?;
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
+
+    Ok(())
 }
 
-fn convert_coords_from_lambert_93_to_gps(x: f64, y: f64) -> (f64, f64) {
+fn convert_coords_to_gps(x: f64, y: f64, epsg_code: u16) -> Result<(f64, f64), Error> {
     let echo = Command::new("echo")
         .arg(format!("{:.1} {:.1}", x, y))
         .stdout(Stdio::piped())
         .spawn()
-        .unwrap();
-
-    let output = Command::new("cs2cs")
-        .args(["+init=epsg:2154", "+to", "+init=epsg:4326", "-f", "%.8f"])
-        .stdin(Stdio::from(echo.stdout.unwrap()))
-        .output()
-        .expect("failed to execute proj command");
-
-    if !output.status.success() {
-        panic!("Proj conversion failed.")
-    }
-
-    let result = String::from_utf8(output.stdout).unwrap();
-    let coords: Vec<&str> = result.trim().split_whitespace().collect();
+        .map_err(|source| Error::CommandSpawn {
+            command: "echo".to_owned(),
+            source,
+        })?;
+
+    let Some(echo_stdout) = echo.stdout else {
+        return Err(Error::Proj(
+            "Could not pipe coordinates to cs2cs".to_owned(),
+        ));
+    };
+
+    let output = run_command(
+        Command::new("cs2cs")
+            .arg(format!("+init=epsg:{}", epsg_code))
+            .args(["+to", "+init=epsg:4326", "-f", "%.8f"])
+            .stdin(Stdio::from(echo_stdout)),
+    )?;
+
+    let result = String::from_utf8_lossy(&output.stdout);
+    let coords: Vec<&str> = result.split_whitespace().collect();
 
     if coords.len() < 2 {
-        panic!("Wrong Proj conversion result format")
+        return Err(Error::Proj(format!(
+            "Wrong result format: {:?}",
+            result.trim()
+        )));
     }
 
-    let lon: f64 = coords[0].parse().expect("Failed to parse longitude");
-    let lat: f64 = coords[1].parse().expect("Failed to parse latitude");
+    let lon: f64 = coords[0]
+        .parse()
+        .map_err(|_| Error::Proj(format!("Failed to parse longitude {:?}", coords[0])))?;
+    let lat: f64 = coords[1]
+        .parse()
+        .map_err(|_| Error::Proj(format!("Failed to parse latitude {:?}", coords[1])))?;
 
-    return (lon, lat);
+    Ok((lon, lat))
 }
@@ -0,0 +1,325 @@
+use crate::{config::ConfigError, tile::Tile};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    Io(io::Error),
+    File {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Las {
+        path: PathBuf,
+        source: las::Error,
+    },
+    Tiff {
+        path: PathBuf,
+        source: tiff::TiffError,
+    },
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Shapefile {
+        path: PathBuf,
+        source: shapefile::Error,
+    },
+    MissingShapefileField {
+        path: PathBuf,
+        field: String,
+    },
+    UnexpectedRasterFormat {
+        path: PathBuf,
+        expected: &'static str,
+    },
+    EmptyRaster(PathBuf),
+    MissingFile(PathBuf),
+    NotATileDir(PathBuf),
+    NoGroundPoints(PathBuf),
+    MissingCrs(PathBuf),
+    MissingTileCrs(PathBuf),
+    UnknownCrs,
+    MixedCrs {
+        first_path: PathBuf,
+        first_epsg_code: u16,
+        second_path: PathBuf,
+        second_epsg_code: u16,
+    },
+    InvalidCopc {
+        path: PathBuf,
+        reason: &'static str,
+    },
+    InvalidPng(PathBuf),
+    Download(reqwest::Error),
+    Proj(String),
+    Panicked(String),
+    CommandSpawn {
+        command: String,
+        source: io::Error,
+    },
+    CommandFailed {
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    Stage {
+        stage: Stage,
+        tile: Option<PathBuf>,
+        source: Box<Error>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    TilesDiscovery,
+    Lidar,
+    Download,
+    Render,
+    Vegetation,
+    Dem,
+    Contours,
+    Cliffs,
+    Trees,
+    Vectors,
+    FullMap,
+    Merge,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::TilesDiscovery => "tiles discovery",
+            Stage::Lidar => "LiDAR processing",
+            Stage::Download => "osm file download",
+            Stage::Render => "rendering",
+            Stage::Vegetation => "vegetation rendering",
+            Stage::Dem => "DEM generation",
+            Stage::Contours => "contours rendering",
+            Stage::Cliffs => "cliffs rendering",
+            Stage::Trees => "trees rendering",
+            Stage::Vectors => "vectors rendering",
+            Stage::FullMap => "full map rendering",
+            Stage::Merge => "maps merging",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Stage {
+    /// A stable identifier for the stage, used as a key in tile manifests.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Stage::TilesDiscovery => "tiles_discovery",
+            Stage::Lidar => "lidar",
+            Stage::Download => "download",
+            Stage::Render => "render",
+            Stage::Vegetation => "vegetation",
+            Stage::Dem => "dem",
+            Stage::Contours => "contours",
+            Stage::Cliffs => "cliffs",
+            Stage::Trees => "trees",
+            Stage::Vectors => "vectors",
+            Stage::FullMap => "full_map",
+            Stage::Merge => "merge",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(error) => write!(f, "{}", error),
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::File { path, .. } => write!(f, "Could not access file {:?}", path),
+            Error::Las { path, .. } => write!(f, "Could not read LiDAR file {:?}", path),
+            Error::Tiff { path, .. } => write!(f, "Could not decode tif image {:?}", path),
+            Error::Image { path, .. } => write!(f, "Could not save image {:?}", path),
+            Error::Shapefile { path, .. } => write!(f, "Could not access shapefile {:?}", path),
+            Error::MissingShapefileField { path, field } => {
+                write!(f, "Field '{}' is not within dataset {:?}", field, path)
+            }
+            Error::UnexpectedRasterFormat { path, expected } => {
+                write!(f, "Expected {} band data in raster {:?}", expected, path)
+            }
+            Error::EmptyRaster(path) => write!(f, "Raster {:?} has no pixels", path),
+            Error::MissingFile(path) => write!(f, "File {:?} does not exist", path),
+            Error::NotATileDir(path) => write!(
+                f,
+                "Directory {:?} is not a tile directory, run the LiDAR stage on it first",
+                path
+            ),
+            Error::NoGroundPoints(path) => {
+                write!(f, "No ground point found in LiDAR file {:?}", path)
+            }
+            Error::MissingCrs(path) => write!(
+                f,
+                "No CRS found in LiDAR file {:?}, set \"epsg_code\" in the config",
+                path
+            ),
+            Error::MissingTileCrs(path) => write!(
+                f,
+                "Tile {:?} was processed before CRSs were recorded, set \"epsg_code\" in the config",
+                path
+            ),
+            Error::UnknownCrs => write!(
+                f,
+                "The CRS of the coordinates is unknown, set \"epsg_code\" in the config"
+            ),
+            Error::MixedCrs {
+                first_path,
+                first_epsg_code,
+                second_path,
+                second_epsg_code,
+            } => write!(
+                f,
+                "LiDAR files {:?} (EPSG:{}) and {:?} (EPSG:{}) have different CRSs",
+                first_path, first_epsg_code, second_path, second_epsg_code
+            ),
+            Error::InvalidCopc { path, reason } => {
+                write!(f, "Invalid COPC file {:?}: {}", path, reason)
+            }
+            Error::InvalidPng(path) => {
+                write!(f, "Could not encode or decode png image {:?}", path)
+            }
+            Error::Download(_) => write!(f, "Could not download osm file"),
+            Error::Proj(message) => write!(f, "Proj conversion failed: {}", message),
+            Error::Panicked(message) => write!(f, "Unexpected panic: {}", message),
+            Error::CommandSpawn { command, .. } => {
+                write!(f, "Could not execute command `{}`", command)
+            }
+            Error::CommandFailed {
+                command,
+                status,
+                stderr,
+            } => write!(
+                f,
+                "Command `{}` failed with {}: {}",
+                command, status, stderr
+            ),
+            Error::Stage {
+                stage,
+                tile: Some(tile),
+                ..
+            } => write!(f, "The {} stage failed for tile {:?}", stage, tile),
+            Error::Stage {
+                stage, tile: None, ..
+            } => write!(f, "The {} stage failed", stage),
+        }
+    }
+}
+
+impl Error {
+    /// The most specific pipeline stage the error happened in, if any.
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Error::Stage { stage, source, .. } => source.stage().or(Some(*stage)),
+            _ => None,
+        }
+    }
+
+    /// The error message followed by the messages of all its sources.
+    pub fn full_message(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = std::error::Error::source(self);
+
+        while let Some(cause) = source {
+            message.push_str(": ");
+            message.push_str(&cause.to_string());
+            source = cause.source();
+        }
+
+        message
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(source) => Some(source),
+            Error::File { source, .. } => Some(source),
+            Error::Las { source, .. } => Some(source),
+            Error::Tiff { source, .. } => Some(source),
+            Error::Image { source, .. } => Some(source),
+            Error::Shapefile { source, .. } => Some(source),
+            Error::Download(source) => Some(source),
+            Error::CommandSpawn { source, .. } => Some(source),
+            Error::Stage { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Self {
+        Error::Config(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Download(error)
+    }
+}
+
+pub(crate) fn file_error(path: &Path) -> impl FnOnce(io::Error) -> Error {
+    let path = path.to_path_buf();
+    move |source| Error::File { path, source }
+}
+
+pub(crate) fn las_error(path: &Path) -> impl FnOnce(las::Error) -> Error {
+    let path = path.to_path_buf();
+    move |source| Error::Las { path, source }
+}
+
+pub(crate) fn tiff_error(path: &Path) -> impl FnOnce(tiff::TiffError) -> Error {
+    let path = path.to_path_buf();
+    move |source| Error::Tiff { path, source }
+}
+
+pub(crate) fn image_error(path: &Path) -> impl FnOnce(image::ImageError) -> Error {
+    let path = path.to_path_buf();
+    move |source| Error::Image { path, source }
+}
+
+pub(crate) fn shapefile_error(path: &Path) -> impl FnOnce(shapefile::Error) -> Error {
+    let path = path.to_path_buf();
+    move |source| Error::Shapefile { path, source }
+}
+
+pub(crate) trait StageResultExt<T> {
+    /// Wraps the error with the stage and the tile it happened in.
+    fn for_tile(self, stage: Stage, tile: &Tile) -> Result<T, Error>;
+
+    /// Wraps the error with the stage it happened in.
+    fn in_stage(self, stage: Stage) -> Result<T, Error>;
+}
+
+impl<T> StageResultExt<T> for Result<T, Error> {
+    fn for_tile(self, stage: Stage, tile: &Tile) -> Result<T, Error> {
+        self.map_err(|source| Error::Stage {
+            stage,
+            tile: Some(tile.dir_path.clone()),
+            source: Box::new(source),
+        })
+    }
+
+    fn in_stage(self, stage: Stage) -> Result<T, Error> {
+        self.map_err(|source| Error::Stage {
+            stage,
+            tile: None,
+            source: Box::new(source),
+        })
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{config::FillMethod, raster::Raster};
+use std::f64::consts::PI;
+
+/// The nearest valid cells of a hole are searched in this many directions spread evenly around
+/// it, so a hole is interpolated from all its sides and not only from its closest edge.
+const SEARCH_DIRECTIONS: usize = 16;
+const LAPLACIAN_MAX_ITERATIONS: usize = 1000;
+/// The largest change of a cell, in meters, under which the Laplacian interpolation stops
+const LAPLACIAN_TOLERANCE: f64 = 0.001;
+
+/// Interpolates the nodata cells of a raster that have a valid cell closer than `max_distance`
+/// meters. Returns the filled raster and a mask set to 1 on the interpolated cells and 0
+/// elsewhere, so later stages know the terrain there is synthetic.
+pub fn fill_holes(raster: &Raster, method: FillMethod, max_distance: f64) -> (Raster, Raster) {
+    let mut filled_raster = raster.clone();
+    let mut interpolated_mask = Raster::new(
+        raster.width,
+        raster.height,
+        raster.min_x,
+        raster.max_y,
+        raster.resolution,
+        None,
+        0.,
+    );
+
+    let max_steps = (max_distance / raster.resolution).floor() as i64;
+    let mut interpolated_cells: Vec<(usize, usize)> = vec![];
+
+    for row in 0..raster.height {
+        for column in 0..raster.width {
+            if !raster.is_nodata(raster.get(column, row)) {
+                continue;
+            }
+
+            if let Some(value) = interpolate_inverse_distance(raster, column, row, max_steps) {
+                filled_raster.set(column, row, value);
+                interpolated_mask.set(column, row, 1.);
+                interpolated_cells.push((column, row));
+            }
+        }
+    }
+
+    // The inverse distance weighting is a good first guess for the relaxation to converge fast
+    if method == FillMethod::Laplacian {
+        relax_laplacian(&mut filled_raster, &interpolated_cells);
+    }
+
+    (filled_raster, interpolated_mask)
+}
+
+/// The mean of the first valid cells met in every search direction, weighted by their inverse
+/// squared distance, if any is closer than the maximum number of steps.
+fn interpolate_inverse_distance(
+    raster: &Raster,
+    column: usize,
+    row: usize,
+    max_steps: i64,
+) -> Option<f64> {
+    let mut weighted_sum = 0.;
+    let mut weights_sum = 0.;
+
+    for direction in 0..SEARCH_DIRECTIONS {
+        let angle = 2. * PI * direction as f64 / SEARCH_DIRECTIONS as f64;
+        let (step_x, step_y) = (angle.cos(), angle.sin());
+
+        for step in 1..=max_steps {
+            let other_column = (column as f64 + step_x * step as f64).round() as i64;
+            let other_row = (row as f64 + step_y * step as f64).round() as i64;
+
+            if other_column < 0
+                || other_row < 0
+                || other_column >= raster.width as i64
+                || other_row >= raster.height as i64
+            {
+                break;
+            }
+
+            let value = raster.get(other_column as usize, other_row as usize);
+
+            if raster.is_nodata(value) {
+                continue;
+            }
+
+            let squared_distance = ((other_column - column as i64).pow(2)
+                + (other_row - row as i64).pow(2)) as f64
+                * raster.resolution
+                * raster.resolution;
+
+            weighted_sum += value / squared_distance;
+            weights_sum += 1. / squared_distance;
+            break;
+        }
+    }
+
+    if weights_sum == 0. {
+        return None;
+    }
+
+    Some(weighted_sum / weights_sum)
+}
+
+/// Solves the Laplace equation on the interpolated cells with Gauss-Seidel iterations, the valid
+/// cells around the holes staying fixed. Every cell ends up as the mean of its 4 neighbors.
+fn relax_laplacian(raster: &mut Raster, interpolated_cells: &[(usize, usize)]) {
+    for _ in 0..LAPLACIAN_MAX_ITERATIONS {
+        let mut max_change: f64 = 0.;
+
+        for &(column, row) in interpolated_cells {
+            let neighbors = [
+                (column as i64 - 1, row as i64),
+                (column as i64 + 1, row as i64),
+                (column as i64, row as i64 - 1),
+                (column as i64, row as i64 + 1),
+            ];
+
+            let mut sum = 0.;
+            let mut count = 0;
+
+            for (neighbor_column, neighbor_row) in neighbors {
+                if neighbor_column < 0
+                    || neighbor_row < 0
+                    || neighbor_column >= raster.width as i64
+                    || neighbor_row >= raster.height as i64
+                {
+                    continue;
+                }
+
+                let value = raster.get(neighbor_column as usize, neighbor_row as usize);
+
+                // Cells too far from any valid cell are still holes
+                if raster.is_nodata(value) {
+                    continue;
+                }
+
+                sum += value;
+                count += 1;
+            }
+
+            if count == 0 {
+                continue;
+            }
+
+            let value = sum / count as f64;
+            max_change = max_change.max((value - raster.get(column, row)).abs());
+            raster.set(column, row, value);
+        }
+
+        if max_change < LAPLACIAN_TOLERANCE {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::NO_DATA_VALUE;
+
+    /// A 9 by 9 raster sloping towards the east, 1 meter per cell, with the given nodata cells.
+    fn create_raster(holes: &[(usize, usize)]) -> Raster {
+        let mut raster = Raster::new(9, 9, 0., 9., 1., Some(NO_DATA_VALUE), NO_DATA_VALUE);
+
+        for row in 0..9 {
+            for column in 0..9 {
+                raster.set(column, row, 100. + column as f64);
+            }
+        }
+
+        for &(column, row) in holes {
+            raster.set(column, row, NO_DATA_VALUE);
+        }
+
+        raster
+    }
+
+    #[test]
+    fn fills_an_interior_hole() {
+        let holes = [(3, 4), (4, 4), (5, 4), (4, 3), (4, 5)];
+        let raster = create_raster(&holes);
+
+        for method in [FillMethod::InverseDistance, FillMethod::Laplacian] {
+            let (filled_raster, interpolated_mask) = fill_holes(&raster, method, 10.);
+
+            for &(column, row) in &holes {
+                let value = filled_raster.get(column, row);
+
+                // Every hole cell is surrounded by the same values on its west and east
+                assert!((value - (100. + column as f64)).abs() < 0.5, "{}", value);
+                assert_eq!(interpolated_mask.get(column, row), 1.);
+            }
+        }
+    }
+
+    #[test]
+    fn fills_a_hole_touching_the_border() {
+        let holes = [(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)];
+        let raster = create_raster(&holes);
+        let (filled_raster, interpolated_mask) =
+            fill_holes(&raster, FillMethod::InverseDistance, 10.);
+
+        for &(column, row) in &holes {
+            let value = filled_raster.get(column, row);
+
+            // Only the valid cells on the east and south sides of the hole are found
+            assert!(!filled_raster.is_nodata(value));
+            assert!((100. ..=103.).contains(&value), "{}", value);
+            assert_eq!(interpolated_mask.get(column, row), 1.);
+        }
+    }
+
+    #[test]
+    fn leaves_cells_too_far_from_valid_cells_as_holes() {
+        let holes: Vec<(usize, usize)> = (0..9)
+            .flat_map(|row| (0..4).map(move |column| (column, row)))
+            .collect();
+        let raster = create_raster(&holes);
+        let (filled_raster, interpolated_mask) = fill_holes(&raster, FillMethod::Laplacian, 2.);
+
+        assert!(filled_raster.is_nodata(filled_raster.get(0, 4)));
+        assert!(filled_raster.is_nodata(filled_raster.get(1, 4)));
+        assert_eq!(interpolated_mask.get(1, 4), 0.);
+        assert!(!filled_raster.is_nodata(filled_raster.get(2, 4)));
+        assert_eq!(interpolated_mask.get(2, 4), 1.);
+    }
+
+    #[test]
+    fn keeps_an_all_nodata_raster_empty() {
+        let raster = Raster::new(5, 5, 0., 5., 1., Some(NO_DATA_VALUE), NO_DATA_VALUE);
+
+        for method in [FillMethod::InverseDistance, FillMethod::Laplacian] {
+            let (filled_raster, interpolated_mask) = fill_holes(&raster, method, 10.);
+
+            assert!(filled_raster
+                .pixels
+                .iter()
+                .all(|&value| filled_raster.is_nodata(value)));
+            assert!(interpolated_mask.pixels.iter().all(|&value| value == 0.));
+        }
+    }
+
+    #[test]
+    fn only_marks_the_interpolated_cells_in_the_mask() {
+        let raster = create_raster(&[(6, 6)]);
+        let (filled_raster, interpolated_mask) =
+            fill_holes(&raster, FillMethod::InverseDistance, 10.);
+
+        assert_eq!(interpolated_mask.nodata, None);
+
+        for row in 0..9 {
+            for column in 0..9 {
+                let expected_mask = if (column, row) == (6, 6) { 1. } else { 0. };
+                assert_eq!(interpolated_mask.get(column, row), expected_mask);
+
+                if (column, row) != (6, 6) {
+                    assert_eq!(filled_raster.get(column, row), raster.get(column, row));
+                }
+            }
+        }
+    }
+}
@@ -3,9 +3,15 @@ use std::{
     time::Instant,
 };
 
-use crate::{canvas::Canvas, tile::Tile};
-
-pub fn render_full_map_to_png(tile: &Tile, image_width: u32, image_height: u32, skip_vector: bool) {
+use crate::{canvas::Canvas, error::Error, tile::Tile};
+
+pub fn render_full_map_to_png(
+    tile: &Tile,
+    image_width: u32,
+    image_height: u32,
+    has_undergrowth: bool,
+    skip_vector: bool,
+) -> Result<(), Error> {
     print!("Rendering map to png");
     let _ = stdout().flush();
     let start = Instant::now();
@@ -13,25 +19,37 @@ pub fn render_full_map_to_png(tile: &Tile, image_width: u32, image_height: u32,
     let mut full_map_canvas = Canvas::new(image_width as i32, image_height as i32);
 
     let cliffs_path = tile.dir_path.join("cliffs.png");
-    let mut cliff_canvas = Canvas::load_from(&cliffs_path.to_str().unwrap());
+    let mut cliff_canvas = Canvas::load_from(&cliffs_path)?;
     let vegetation_path = tile.dir_path.join("vegetation.png");
-    let mut vegetation_canvas = Canvas::load_from(&vegetation_path.to_str().unwrap());
+    let mut vegetation_canvas = Canvas::load_from(&vegetation_path)?;
     let contours_path = tile.dir_path.join("contours.png");
-    let mut contours_canvas = Canvas::load_from(&contours_path.to_str().unwrap());
+    let mut contours_canvas = Canvas::load_from(&contours_path)?;
+    let trees_path = tile.dir_path.join("trees.png");
+    let mut trees_canvas = Canvas::load_from(&trees_path)?;
 
     full_map_canvas.overlay(&mut vegetation_canvas, 0.0, 0.0);
+
+    if has_undergrowth {
+        let undergrowth_path = tile.dir_path.join("undergrowth.png");
+        let mut undergrowth_canvas = Canvas::load_from(&undergrowth_path)?;
+        full_map_canvas.overlay(&mut undergrowth_canvas, 0.0, 0.0);
+    }
+
     full_map_canvas.overlay(&mut contours_canvas, 0.0, 0.0);
     full_map_canvas.overlay(&mut cliff_canvas, 0.0, 0.0);
+    full_map_canvas.overlay(&mut trees_canvas, 0.0, 0.0);
 
     if !skip_vector {
         let vectors_path = tile.dir_path.join("vectors.png");
-        let mut vectors_canvas = Canvas::load_from(&vectors_path.to_str().unwrap());
+        let mut vectors_canvas = Canvas::load_from(&vectors_path)?;
         full_map_canvas.overlay(&mut vectors_canvas, 0.0, 0.0);
     }
 
     let full_map_path = tile.dir_path.join("full-map.png");
-    full_map_canvas.save_as(&full_map_path.to_str().unwrap());
+    full_map_canvas.save_as(&full_map_path)?;
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
+
+    Ok(())
 }
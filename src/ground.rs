@@ -0,0 +1,312 @@
+use crate::{
+    config::GroundClassificationConfig,
+    raster::{Raster, NO_DATA_VALUE},
+};
+
+const LOW_NOISE_CLASSIFICATION: u8 = 7;
+
+/// Lowest point elevation of every cell, the input of the ground filter.
+pub struct MinimumSurface {
+    raster: Raster,
+}
+
+impl MinimumSurface {
+    pub fn new(width: f64, height: f64, min_x: f64, max_y: f64, cell_size: f64) -> MinimumSurface {
+        MinimumSurface {
+            raster: Raster::new(
+                (width / cell_size).ceil() as usize,
+                (height / cell_size).ceil() as usize,
+                min_x,
+                max_y,
+                cell_size,
+                Some(NO_DATA_VALUE),
+                NO_DATA_VALUE,
+            ),
+        }
+    }
+
+    pub fn add(&mut self, x: f64, y: f64, z: f64, classification: u8) {
+        // Low points would be mistaken for the ground and pull the whole surface down
+        if classification == LOW_NOISE_CLASSIFICATION {
+            return;
+        }
+
+        if let Some((column, row)) = self.raster.get_cell_index(x, y) {
+            let current = self.raster.get(column, row);
+
+            if self.raster.is_nodata(current) || z < current {
+                self.raster.set(column, row, z);
+            }
+        }
+    }
+
+    /// Simple morphological filter (Pingel et al., 2013): cells rising above a progressive
+    /// morphological opening of the surface more than the terrain slope allows are objects
+    /// (vegetation, buildings), the remaining ones are interpolated into a ground surface.
+    /// Returns None if the surface is empty.
+    pub fn into_ground_surface(self, config: &GroundClassificationConfig) -> Option<Raster> {
+        let mut minimum_surface = self.raster;
+        let empty_cells: Vec<bool> = minimum_surface
+            .pixels
+            .iter()
+            .map(|value| *value == NO_DATA_VALUE)
+            .collect();
+
+        if !fill_empty_cells(&mut minimum_surface) {
+            return None;
+        }
+
+        let mut objects = empty_cells;
+        let mut surface = minimum_surface.clone();
+        let max_radius = (config.max_window_size / config.cell_size).ceil() as usize;
+
+        for radius in 1..=max_radius {
+            let opened = open(&surface, radius);
+            let threshold = config.slope * radius as f64 * config.cell_size;
+
+            for (index, is_object) in objects.iter_mut().enumerate() {
+                if surface.pixels[index] - opened.pixels[index] > threshold {
+                    *is_object = true;
+                }
+            }
+
+            surface = opened;
+        }
+
+        for (pixel, is_object) in minimum_surface.pixels.iter_mut().zip(&objects) {
+            if *is_object {
+                *pixel = NO_DATA_VALUE;
+            }
+        }
+
+        if !fill_empty_cells(&mut minimum_surface) {
+            return None;
+        }
+
+        Some(minimum_surface)
+    }
+}
+
+/// Whether a point lies close enough to the ground surface to be a ground point.
+pub fn is_ground_point(
+    ground_surface: &Raster,
+    x: f64,
+    y: f64,
+    z: f64,
+    config: &GroundClassificationConfig,
+) -> bool {
+    ground_surface
+        .get_value_at(x, y)
+        .is_some_and(|elevation| (z - elevation).abs() <= config.elevation_threshold)
+}
+
+/// Sets empty cells to the mean of their non empty neighbors, one ring of cells around the non
+/// empty ones at a time. Returns false if the raster has no value at all.
+fn fill_empty_cells(raster: &mut Raster) -> bool {
+    if raster.pixels.iter().all(|value| *value == NO_DATA_VALUE) {
+        return false;
+    }
+
+    // The cells with a value, or to be set by the current ring
+    let mut is_reached: Vec<bool> = raster
+        .pixels
+        .iter()
+        .map(|value| *value != NO_DATA_VALUE)
+        .collect();
+
+    let mut frontier: Vec<(usize, usize)> = vec![];
+
+    for row in 0..raster.height {
+        for column in 0..raster.width {
+            if raster.get(column, row) == NO_DATA_VALUE
+                && get_neighbors(raster, column, row).any(|(other_column, other_row)| {
+                    is_reached[other_row * raster.width + other_column]
+                })
+            {
+                frontier.push((column, row));
+            }
+        }
+    }
+
+    for &(column, row) in &frontier {
+        is_reached[row * raster.width + column] = true;
+    }
+
+    while !frontier.is_empty() {
+        // The whole ring is computed before being set, so its cells do not depend on each other
+        let values: Vec<f64> = frontier
+            .iter()
+            .map(|&(column, row)| {
+                let (sum, count) = get_neighbors(raster, column, row)
+                    .map(|(other_column, other_row)| raster.get(other_column, other_row))
+                    .filter(|value| *value != NO_DATA_VALUE)
+                    .fold((0., 0), |(sum, count), value| (sum + value, count + 1));
+
+                sum / count as f64
+            })
+            .collect();
+
+        for (&(column, row), value) in frontier.iter().zip(values) {
+            raster.set(column, row, value);
+        }
+
+        let mut next_frontier: Vec<(usize, usize)> = vec![];
+
+        for &(column, row) in &frontier {
+            for (other_column, other_row) in get_neighbors(raster, column, row) {
+                let index = other_row * raster.width + other_column;
+
+                if !is_reached[index] {
+                    is_reached[index] = true;
+                    next_frontier.push((other_column, other_row));
+                }
+            }
+        }
+
+        frontier = next_frontier;
+    }
+
+    true
+}
+
+/// The cells of the 3 by 3 window around a cell, the cell included.
+fn get_neighbors(
+    raster: &Raster,
+    column: usize,
+    row: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    let columns = column.saturating_sub(1)..(column + 2).min(raster.width);
+
+    (row.saturating_sub(1)..(row + 2).min(raster.height)).flat_map(move |other_row| {
+        columns
+            .clone()
+            .map(move |other_column| (other_column, other_row))
+    })
+}
+
+/// Morphological opening (erosion then dilation) with a square window.
+fn open(raster: &Raster, radius: usize) -> Raster {
+    let eroded = filter_window(raster, radius, f64::min);
+    filter_window(&eroded, radius, f64::max)
+}
+
+/// Applies a min or max filter over a square window, separately on rows then columns.
+fn filter_window(raster: &Raster, radius: usize, reduce: fn(f64, f64) -> f64) -> Raster {
+    let mut horizontal = raster.clone();
+
+    for row in 0..raster.height {
+        for column in 0..raster.width {
+            let start = column.saturating_sub(radius);
+            let end = (column + radius + 1).min(raster.width);
+            let value = (start..end)
+                .map(|window_column| raster.get(window_column, row))
+                .reduce(reduce)
+                .unwrap();
+
+            horizontal.set(column, row, value);
+        }
+    }
+
+    let mut filtered = horizontal.clone();
+
+    for row in 0..raster.height {
+        for column in 0..raster.width {
+            let start = row.saturating_sub(radius);
+            let end = (row + radius + 1).min(raster.height);
+            let value = (start..end)
+                .map(|window_row| horizontal.get(column, window_row))
+                .reduce(reduce)
+                .unwrap();
+
+            filtered.set(column, row, value);
+        }
+    }
+
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: f64 = 40.;
+
+    /// The ground surface of a 40 by 40 meters area with a point every meter, at the given
+    /// elevation of the coordinates.
+    fn get_ground_surface(elevation: impl Fn(f64, f64) -> f64) -> Raster {
+        let config = GroundClassificationConfig::default();
+        let mut minimum_surface = MinimumSurface::new(SIZE, SIZE, 0., SIZE, config.cell_size);
+
+        for x in 0..SIZE as usize {
+            for y in 0..SIZE as usize {
+                let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+                minimum_surface.add(x, y, elevation(x, y), 1);
+            }
+        }
+
+        minimum_surface.into_ground_surface(&config).unwrap()
+    }
+
+    fn is_ground(ground_surface: &Raster, x: f64, y: f64, z: f64) -> bool {
+        is_ground_point(
+            ground_surface,
+            x,
+            y,
+            z,
+            &GroundClassificationConfig::default(),
+        )
+    }
+
+    #[test]
+    fn keeps_flat_ground() {
+        let ground_surface = get_ground_surface(|_, _| 100.);
+
+        assert!(ground_surface
+            .pixels
+            .iter()
+            .all(|elevation| *elevation == 100.));
+        assert!(is_ground(&ground_surface, 20.5, 20.5, 100.2));
+        assert!(!is_ground(&ground_surface, 20.5, 20.5, 101.));
+    }
+
+    #[test]
+    fn rejects_a_building() {
+        let is_roof = |x: f64, y: f64| (15. ..25.).contains(&x) && (15. ..25.).contains(&y);
+        let ground_surface = get_ground_surface(|x, y| if is_roof(x, y) { 108. } else { 100. });
+
+        assert!(!is_ground(&ground_surface, 20.5, 20.5, 108.));
+        assert!(is_ground(&ground_surface, 20.5, 20.5, 100.));
+        assert!(is_ground(&ground_surface, 5.5, 5.5, 100.));
+    }
+
+    #[test]
+    fn keeps_a_slope_gentler_than_the_threshold() {
+        let ground_surface = get_ground_surface(|x, _| 100. + 0.1 * x);
+
+        for x in [0.5, 20.5, 39.5] {
+            assert!(is_ground(&ground_surface, x, 20.5, 100. + 0.1 * x));
+        }
+    }
+
+    #[test]
+    fn rejects_a_slope_steeper_than_the_threshold() {
+        let ground_surface = get_ground_surface(|x, _| 100. + x);
+
+        // Only the lowest cells are kept, the rest of the slope looks like an object on them
+        assert!(is_ground(&ground_surface, 0.5, 20.5, 100.5));
+        assert!(!is_ground(&ground_surface, 39.5, 20.5, 139.5));
+    }
+
+    #[test]
+    fn fills_empty_cells_from_their_nearest_values() {
+        let mut raster = Raster::new(5, 1, 0., 1., 1., Some(NO_DATA_VALUE), NO_DATA_VALUE);
+        raster.set(0, 0, 10.);
+        raster.set(4, 0, 20.);
+
+        assert!(fill_empty_cells(&mut raster));
+        assert_eq!(raster.pixels, vec![10., 10., 15., 20., 20.]);
+
+        let mut empty_raster = Raster::new(3, 3, 0., 3., 1., Some(NO_DATA_VALUE), NO_DATA_VALUE);
+        assert!(!fill_empty_cells(&mut empty_raster));
+    }
+}
@@ -0,0 +1,44 @@
+mod batch;
+mod buffer;
+mod canvas;
+mod cliffs;
+mod command;
+mod config;
+mod constants;
+mod contours;
+mod crs;
+mod dem;
+mod download;
+mod error;
+mod fill_holes;
+mod full_map;
+mod ground;
+mod lidar;
+mod manifest;
+mod marching_squares;
+mod merge;
+mod pipeline;
+mod png;
+mod points;
+mod pullautin_contours_render;
+mod pullautin_smooth_contours;
+mod raster;
+mod report;
+mod terrain;
+mod tile;
+mod tile_index;
+mod trees;
+mod vectors;
+mod vegetation;
+mod work_queue;
+
+pub use config::{
+    check_config, generate_default_config, get_config, read_config, validate_config, Config,
+    ConfigError, ConfigValidationError, ContoursConfig, DemConfig, DensityNormalization,
+    FillMethod, FormLinesConfig, GroundClassificationConfig, GroundClassificationMode, HeightBand,
+    LidarConfig, TerrainConfig, TreesConfig, UndergrowthConfig,
+};
+pub use error::{Error, Stage};
+pub use pipeline::Pipeline;
+pub use report::{BatchReport, SkippedFile, TileFailure, TileSuccess};
+pub use tile::{NeighborTiles, Tile, TileWithNeighbors};
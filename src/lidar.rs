@@ -1,133 +1,502 @@
-use las::raw::Header;
-use std::fs::{create_dir_all, write, File};
+use crate::config::{Config, DensityNormalization, GroundClassificationMode, LidarConfig};
+use crate::constants::BUFFER;
+use crate::error::{file_error, Error, Stage};
+use crate::ground::{is_ground_point, MinimumSurface};
+use crate::manifest::{
+    get_lidar_fingerprint, get_lidar_outputs, record_point_density, record_tile,
+    run_stage_if_needed,
+};
+use crate::points::{for_each_point_in_extent, Extent};
+use crate::raster::{Raster, NO_DATA_VALUE};
+use crate::tile::Tile;
+use std::fs::create_dir_all;
 use std::io::{stdout, Write};
-use std::path::PathBuf;
-use std::process::{Command, ExitStatus};
 use std::time::Instant;
 
+// Low and high noise points would show up as spikes in the canopy height model
+const NOISE_CLASSES: [u8; 2] = [7, 18];
+
+/// The attributes of a LiDAR point the rasters are computed from, kept in memory so the LiDAR
+/// file is only read once.
+struct TilePoint {
+    x: f64,
+    y: f64,
+    z: f64,
+    classification: u8,
+}
+
+/// Runs the LiDAR stage of a tile unless its manifest shows the rasters are up to date.
+pub fn generate_tile_rasters_if_needed(
+    tile: &Tile,
+    force: bool,
+    config: &Config,
+) -> Result<(), Error> {
+    let mut point_density = None;
+
+    run_stage_if_needed(
+        &tile.dir_path,
+        Stage::Lidar,
+        &get_lidar_fingerprint(tile, config),
+        &get_lidar_outputs(tile, config),
+        force,
+        || {
+            point_density = Some(
+                generate_dem_and_vegetation_density_tiff_images_from_laz_file(tile, &config.lidar)?,
+            );
+
+            Ok(())
+        },
+    )?;
+
+    record_tile(tile)?;
+
+    match point_density {
+        Some(point_density) => record_point_density(&tile.dir_path, point_density),
+        None => Ok(()),
+    }
+}
+
+/// Writes the DEM, point density, canopy height model and height band rasters of the LiDAR file
+/// of a tile. Returns the mean point density of the tile, in points per square meter.
 pub fn generate_dem_and_vegetation_density_tiff_images_from_laz_file(
-    laz_path: &PathBuf,
-    output_dir_path: &PathBuf,
-) {
-    print!("Executing PDAL pipeline");
+    tile: &Tile,
+    lidar_config: &LidarConfig,
+) -> Result<f64, Error> {
+    print!("Rasterizing LiDAR points");
     let _ = stdout().flush();
     let start = Instant::now();
 
-    let mut file = File::open(&laz_path).unwrap();
-    let header = Header::read_from(&mut file).unwrap();
-    let min_x = header.min_x.round() as i64;
-    let min_y = header.min_y.round() as i64;
-    let max_x = header.max_x.round() as i64;
-    let max_y = header.max_y.round() as i64;
-
-    let dem_path = output_dir_path.join("dem.tif");
-    let dem_low_resolution_path = output_dir_path.join("dem-low-resolution.tif");
-    let medium_vegetation_path = output_dir_path.join("medium-vegetation.tif");
-    let high_vegetation_path = output_dir_path.join("high-vegetation.tif");
-    let pipeline_path = output_dir_path.join("pipeline.json");
-    create_dir_all(&output_dir_path).expect("Could not create out dir");
-
-    let gdal_common_options = format!(
-        r#""binmode": true,
-        "origin_x": {},
-        "origin_y": {},
-        "width": {},
-        "height": {},"#,
-        min_x,
-        min_y,
-        max_x - min_x,
-        max_y - min_y
-    );
+    let laz_path = tile.laz_path.as_path();
+    let output_dir_path = tile.dir_path.as_path();
+    let epsg_code = tile.epsg_code;
+    let (min_x, min_y, max_x, max_y) = (tile.min_x, tile.min_y, tile.max_x, tile.max_y);
+    let width = (max_x - min_x) as usize;
+    let height = (max_y - min_y) as usize;
 
-    let gdal_dem_low_resolution_options = format!(
-        r#""binmode": true,
-        "origin_x": {},
-        "origin_y": {},
-        "width": {},
-        "height": {},"#,
-        min_x,
-        min_y,
-        ((max_x - min_x) as f64 / 2.).ceil() as i64,
-        ((max_y - min_y) as f64 / 2.).ceil() as i64
-    );
+    // Only the tile and its buffer are read, COPC files skipping the octree nodes outside of it.
+    // The buffer lets the ground filter see the terrain around the tile edges.
+    let buffer = BUFFER as i64;
+    let extent = Extent {
+        min_x: (min_x - buffer) as f64,
+        min_y: (min_y - buffer) as f64,
+        max_x: (max_x + buffer) as f64,
+        max_y: (max_y + buffer) as f64,
+    };
 
-    let pdal_pipeline = format!(
-        r#"[
-    {:?},
-    {{
-        "type": "writers.gdal",
-        "filename": {:?},
-        "resolution": 1,
-        {}
-        "where": "Classification == 2",
-        "output_type": "mean"
-    }},
-    {{
-        "type": "writers.gdal",
-        "filename": {:?},
-        "resolution": 2,
-        {}
-        "where": "Classification == 2",
-        "output_type": "mean"
-    }},
-    {{
-        "type": "filters.hag_dem",
-        "raster": {:?}
-    }},
-    {{
-        "type":"filters.sample",
-        "cell": 0.5
-    }},
-    {{
-        "type": "filters.assign",
-        "value": "Classification = 4",
-        "where": "Classification != 2 && HeightAboveGround > 0.3 && HeightAboveGround <= 4"
-    }},
-    {{
-        "type": "filters.assign",
-        "value": "Classification = 5",
-        "where": "Classification != 2 && HeightAboveGround > 4 && HeightAboveGround <= 30"
-    }},
-    {{
-        "type": "writers.gdal",
-        "filename": {:?},
-        "resolution": 1,
-        {}
-        "where": "Classification == 4",
-        "output_type": "count"
-    }},
-    {{
-        "type": "writers.gdal",
-        "filename": {:?},
-        "resolution": 1,
-        {}
-        "where": "Classification == 5",
-        "output_type": "count"
-    }}
-]"#,
-        laz_path,
-        dem_path,
-        gdal_common_options,
-        dem_low_resolution_path,
-        gdal_dem_low_resolution_options,
-        dem_path,
-        medium_vegetation_path,
-        gdal_common_options,
-        high_vegetation_path,
-        gdal_common_options,
-    );
+    create_dir_all(output_dir_path).map_err(file_error(output_dir_path))?;
+
+    // Rasters are anchored on the bottom left corner of the tile, so the top of the low
+    // resolution DEM can be one meter above the tile when its height is odd
+    let low_resolution_width = (width as f64 / 2.).ceil() as usize;
+    let low_resolution_height = (height as f64 / 2.).ceil() as usize;
+
+    let new_dem_grids = || {
+        (
+            MeanGrid::new(width, height, min_x as f64, max_y as f64, 1.),
+            MeanGrid::new(
+                low_resolution_width,
+                low_resolution_height,
+                min_x as f64,
+                (min_y + 2 * low_resolution_height as i64) as f64,
+                2.,
+            ),
+        )
+    };
+
+    let ground_classification = &lidar_config.ground_classification;
+    let (mut dem, mut dem_low_resolution) = new_dem_grids();
+    let mut minimum_surface =
+        (ground_classification.mode != GroundClassificationMode::Never).then(|| {
+            MinimumSurface::new(
+                extent.max_x - extent.min_x,
+                extent.max_y - extent.min_y,
+                extent.min_x,
+                extent.max_y,
+                ground_classification.cell_size,
+            )
+        });
+    let mut number_of_points: u64 = 0;
+    let mut number_of_ground_points: u64 = 0;
+    // The points of the buffer are only needed by the ground filter
+    let mut tile_points: Vec<TilePoint> = vec![];
+
+    for_each_point_in_extent(laz_path, &extent, |point| {
+        let classification = u8::from(point.classification);
+        number_of_points += 1;
+
+        if let Some(minimum_surface) = &mut minimum_surface {
+            minimum_surface.add(point.x, point.y, point.z, classification);
+        }
+
+        // The low resolution DEM covers the tile and can go one meter above it
+        if dem_low_resolution.covers(point.x, point.y) {
+            tile_points.push(TilePoint {
+                x: point.x,
+                y: point.y,
+                z: point.z,
+                classification,
+            });
+        }
+
+        if !lidar_config.ground_classes.contains(&classification) {
+            return;
+        }
+
+        number_of_ground_points += 1;
+        dem.add(point.x, point.y, point.z);
+        dem_low_resolution.add(point.x, point.y, point.z);
+    })?;
+
+    let should_classify_ground = match ground_classification.mode {
+        GroundClassificationMode::Always => true,
+        GroundClassificationMode::Never => false,
+        GroundClassificationMode::Auto => {
+            (number_of_ground_points as f64)
+                < number_of_points as f64 * ground_classification.min_ground_ratio
+        }
+    };
+
+    // The ground surface replaces the classification of the file for the rest of the stage
+    let ground_surface = match minimum_surface {
+        Some(minimum_surface) if should_classify_ground => {
+            print!(" (classifying ground points)");
+            let _ = stdout().flush();
 
-    write(&pipeline_path, pdal_pipeline).expect("Unable to write pipeline file");
+            let ground_surface = minimum_surface
+                .into_ground_surface(ground_classification)
+                .ok_or_else(|| Error::NoGroundPoints(laz_path.to_path_buf()))?;
 
-    let pdal_output = Command::new("pdal")
-        .args(["pipeline", &pipeline_path.to_str().unwrap()])
-        .output()
-        .expect("failed to execute pdal pipeline command");
+            (dem, dem_low_resolution) = new_dem_grids();
 
-    if !ExitStatus::success(&pdal_output.status) {
-        println!("{}", String::from_utf8(pdal_output.stderr).unwrap());
+            for point in &tile_points {
+                if is_ground_point(
+                    &ground_surface,
+                    point.x,
+                    point.y,
+                    point.z,
+                    ground_classification,
+                ) {
+                    dem.add(point.x, point.y, point.z);
+                    dem_low_resolution.add(point.x, point.y, point.z);
+                }
+            }
+
+            Some(ground_surface)
+        }
+        _ => None,
+    };
+
+    let is_ground = |point: &TilePoint| match &ground_surface {
+        Some(ground_surface) => is_ground_point(
+            ground_surface,
+            point.x,
+            point.y,
+            point.z,
+            ground_classification,
+        ),
+        None => lidar_config.ground_classes.contains(&point.classification),
+    };
+
+    let dem = dem.into_raster();
+    let dem_low_resolution = dem_low_resolution.into_raster();
+
+    if dem.pixels.iter().all(|value| dem.is_nodata(*value)) {
+        return Err(Error::NoGroundPoints(laz_path.to_path_buf()));
+    }
+
+    let new_count_raster = || Raster::new(width, height, min_x as f64, max_y as f64, 1., None, 0.);
+    let mut point_density = new_count_raster();
+    // The canopy height model is the highest height above ground of every cell, zero on bare ground
+    let mut canopy_height_model = new_count_raster();
+    let mut height_band_rasters: Vec<Raster> = lidar_config
+        .height_bands
+        .iter()
+        .map(|_| new_count_raster())
+        .collect();
+    let mut returns_below_rasters: Vec<Raster> =
+        if lidar_config.density_normalization == DensityNormalization::ReturnsBelow {
+            lidar_config
+                .height_bands
+                .iter()
+                .map(|_| new_count_raster())
+                .collect()
+        } else {
+            vec![]
+        };
+
+    // Thinning the point cloud so the vegetation density does not depend on the density of the
+    // LiDAR survey, keeping the first point of every sampling cell. Normalized densities do not
+    // depend on it already, and need every return to be counted the same way.
+    let should_sample = lidar_config.sampling_cell_size > 0.
+        && lidar_config.density_normalization == DensityNormalization::None;
+    let mut sampling_grid = should_sample.then(|| {
+        SamplingGrid::new(
+            width as f64,
+            height as f64,
+            min_x as f64,
+            max_y as f64,
+            lidar_config.sampling_cell_size,
+        )
+    });
+
+    for point in &tile_points {
+        let Some((column, row)) = point_density.get_cell_index(point.x, point.y) else {
+            continue;
+        };
+
+        increment(&mut point_density, column, row);
+        let is_ground_point = is_ground(point);
+
+        let height_above_ground = if is_ground_point {
+            0.
+        } else {
+            match dem
+                .get_value_at(point.x, point.y)
+                .or_else(|| dem_low_resolution.get_value_at(point.x, point.y))
+            {
+                Some(ground_elevation) => point.z - ground_elevation,
+                None => continue,
+            }
+        };
+
+        for (band, raster) in lidar_config
+            .height_bands
+            .iter()
+            .zip(returns_below_rasters.iter_mut())
+        {
+            if height_above_ground <= band.max_height {
+                increment(raster, column, row);
+            }
+        }
+
+        if is_ground_point {
+            continue;
+        }
+
+        if height_above_ground > canopy_height_model.get(column, row)
+            && !NOISE_CLASSES.contains(&point.classification)
+        {
+            canopy_height_model.set(column, row, height_above_ground);
+        }
+
+        if let Some(sampling_grid) = &mut sampling_grid {
+            if !sampling_grid.insert(point.x, point.y, height_above_ground) {
+                continue;
+            }
+        }
+
+        // Bands can overlap, a point is counted in every band containing it
+        for (band, raster) in lidar_config
+            .height_bands
+            .iter()
+            .zip(height_band_rasters.iter_mut())
+        {
+            if band.contains(height_above_ground) {
+                increment(raster, column, row);
+            }
+        }
+    }
+
+    match lidar_config.density_normalization {
+        DensityNormalization::None => {}
+        DensityNormalization::TotalReturns => {
+            for raster in height_band_rasters.iter_mut() {
+                divide(raster, &point_density);
+            }
+        }
+        DensityNormalization::ReturnsBelow => {
+            for (raster, returns_below) in
+                height_band_rasters.iter_mut().zip(&returns_below_rasters)
+            {
+                divide(raster, returns_below);
+            }
+        }
     }
 
+    dem.write_geotiff(&output_dir_path.join("dem.tif"), epsg_code)?;
+    dem_low_resolution.write_geotiff(&output_dir_path.join("dem-low-resolution.tif"), epsg_code)?;
+    point_density.write_geotiff(&output_dir_path.join("point-density.tif"), epsg_code)?;
+    canopy_height_model.write_geotiff(&output_dir_path.join("chm.tif"), epsg_code)?;
+
+    for (band, raster) in lidar_config.height_bands.iter().zip(&height_band_rasters) {
+        raster.write_geotiff(
+            &output_dir_path.join(format!("{}.tif", band.name)),
+            epsg_code,
+        )?;
+    }
+
+    let mean_point_density =
+        point_density.pixels.iter().sum::<f64>() / point_density.pixels.len().max(1) as f64;
+
     let duration = start.elapsed();
-    println!(" -> Done in {:.1?}", duration);
+    println!(
+        " -> Done in {:.1?} ({:.1} points/m²)",
+        duration, mean_point_density
+    );
+
+    Ok(mean_point_density)
+}
+
+fn increment(raster: &mut Raster, column: usize, row: usize) {
+    let count = raster.get(column, row);
+    raster.set(column, row, count + 1.);
+}
+
+/// Divides every cell by the matching one of the denominator, cells without any return
+/// having a zero density.
+fn divide(raster: &mut Raster, denominator: &Raster) {
+    for (pixel, total) in raster.pixels.iter_mut().zip(&denominator.pixels) {
+        *pixel = if *total > 0. { *pixel / total } else { 0. };
+    }
+}
+
+/// The height slices of every sampling cell already holding a sampled point, stored as one bitset
+/// per cell so the memory only depends on the tile area and not on the number of points. Points
+/// higher than the last slice share it.
+struct SamplingGrid {
+    width: usize,
+    height: usize,
+    min_x: f64,
+    max_y: f64,
+    cell_size: f64,
+    slices: Vec<u64>,
+}
+
+impl SamplingGrid {
+    fn new(width: f64, height: f64, min_x: f64, max_y: f64, cell_size: f64) -> SamplingGrid {
+        let width = (width / cell_size).ceil() as usize;
+        let height = (height / cell_size).ceil() as usize;
+
+        SamplingGrid {
+            width,
+            height,
+            min_x,
+            max_y,
+            cell_size,
+            slices: vec![0; width * height],
+        }
+    }
+
+    /// Marks the slice of a point as sampled, returning false if it already was.
+    fn insert(&mut self, x: f64, y: f64, height_above_ground: f64) -> bool {
+        // Points on the right and bottom edges of the tile belong to the last cells
+        let column = (((x - self.min_x) / self.cell_size).floor().max(0.) as usize)
+            .min(self.width.saturating_sub(1));
+        let row = (((self.max_y - y) / self.cell_size).floor().max(0.) as usize)
+            .min(self.height.saturating_sub(1));
+        let slice =
+            ((height_above_ground / self.cell_size).floor().max(0.) as u32).min(u64::BITS - 1);
+
+        let Some(cell_slices) = self.slices.get_mut(row * self.width + column) else {
+            return true;
+        };
+
+        let slice_mask = 1 << slice;
+        let is_new = *cell_slices & slice_mask == 0;
+        *cell_slices |= slice_mask;
+        is_new
+    }
+}
+
+/// Accumulates point elevations to compute their mean on every cell of a raster.
+struct MeanGrid {
+    sums: Raster,
+    counts: Vec<u32>,
+}
+
+impl MeanGrid {
+    fn new(width: usize, height: usize, min_x: f64, max_y: f64, resolution: f64) -> MeanGrid {
+        MeanGrid {
+            sums: Raster::new(width, height, min_x, max_y, resolution, None, 0.),
+            counts: vec![0; width * height],
+        }
+    }
+
+    fn covers(&self, x: f64, y: f64) -> bool {
+        self.sums.get_cell_index(x, y).is_some()
+    }
+
+    fn add(&mut self, x: f64, y: f64, z: f64) {
+        if let Some((column, row)) = self.sums.get_cell_index(x, y) {
+            let sum = self.sums.get(column, row);
+            self.sums.set(column, row, sum + z);
+            self.counts[row * self.sums.width + column] += 1;
+        }
+    }
+
+    fn into_raster(self) -> Raster {
+        let mut raster = self.sums;
+        raster.nodata = Some(NO_DATA_VALUE);
+
+        for (pixel, count) in raster.pixels.iter_mut().zip(self.counts) {
+            *pixel = if count == 0 {
+                NO_DATA_VALUE
+            } else {
+                *pixel / count as f64
+            };
+        }
+
+        raster
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_one_point_per_cube() {
+        let mut sampling_grid = SamplingGrid::new(10., 10., 1000., 2000., 0.5);
+
+        assert!(sampling_grid.insert(1000.1, 1999.9, 1.1));
+        assert!(!sampling_grid.insert(1000.4, 1999.6, 1.4));
+        // The cubes above and next to it are still empty
+        assert!(sampling_grid.insert(1000.1, 1999.9, 1.6));
+        assert!(sampling_grid.insert(1000.6, 1999.9, 1.1));
+        assert!(sampling_grid.insert(1000.1, 1999.4, 1.1));
+    }
+
+    #[test]
+    fn samples_the_points_of_the_tile_edges_in_the_last_cubes() {
+        let mut sampling_grid = SamplingGrid::new(10., 10., 1000., 2000., 0.5);
+
+        assert!(sampling_grid.insert(1010., 1990., 1.));
+        assert!(!sampling_grid.insert(1009.8, 1990.2, 1.2));
+        // Points outside of the tile are clamped to it
+        assert!(!sampling_grid.insert(1010.2, 1989.8, 1.2));
+    }
+
+    #[test]
+    fn shares_the_first_and_last_cubes_beyond_the_sampled_heights() {
+        let mut sampling_grid = SamplingGrid::new(1., 1., 0., 1., 0.5);
+
+        assert!(sampling_grid.insert(0.2, 0.2, -0.3));
+        assert!(!sampling_grid.insert(0.2, 0.2, 0.2));
+        // The last of the 64 cubes starts 31.5 meters above the ground
+        assert!(sampling_grid.insert(0.2, 0.2, 32.));
+        assert!(!sampling_grid.insert(0.2, 0.2, 100.));
+        assert!(!sampling_grid.insert(0.2, 0.2, 31.6));
+        assert!(sampling_grid.insert(0.2, 0.2, 31.4));
+    }
+
+    #[test]
+    fn averages_the_elevations_of_every_cell() {
+        let mut mean_grid = MeanGrid::new(2, 2, 0., 2., 1.);
+        mean_grid.add(0.2, 1.8, 10.);
+        mean_grid.add(0.8, 1.2, 12.);
+        // On the bottom right corner of the grid
+        mean_grid.add(2., 0., 20.);
+        mean_grid.add(2.1, 0.5, 100.);
+
+        assert!(mean_grid.covers(2., 0.));
+        assert!(!mean_grid.covers(2.1, 0.5));
+
+        let raster = mean_grid.into_raster();
+
+        assert_eq!(raster.get(0, 0), 11.);
+        assert_eq!(raster.get(1, 1), 20.);
+        // Cells without points are nodata
+        assert!(raster.is_nodata(raster.get(1, 0)));
+        assert!(raster.is_nodata(raster.get(0, 1)));
+    }
 }
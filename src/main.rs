@@ -1,99 +1,142 @@
-mod batch;
-mod buffer;
-mod canvas;
 mod cli;
-mod cliffs;
-mod config;
-mod constants;
-mod contours;
-mod dem;
-mod download;
-mod full_map;
-mod lidar;
-mod merge;
-mod png;
-mod pullautin_contours_render;
-mod pullautin_smooth_contours;
-mod tile;
-mod vectors;
-mod vegetation;
-
-use batch::batch;
+
+use cassini::{check_config, generate_default_config, get_config, Pipeline};
 use clap::Parser;
-use cli::Args;
-use config::generate_default_config;
-use constants::INCH;
-use download::download_osm_file_if_needed;
-use las::raw::Header;
-use lidar::generate_dem_and_vegetation_density_tiff_images_from_laz_file;
-use png::generate_png_from_dem_vegetation_density_tiff_images_and_vector_file;
-use std::{fs::File, path::Path, time::Instant};
-use tile::{NeighborTiles, Tile};
+use cli::{Args, BatchArgs, Command};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::exit,
+    time::Instant,
+};
 
 fn main() {
     let args = Args::parse();
 
-    if args.default_config {
-        generate_default_config();
-        return;
+    match &args.command {
+        Command::Init => {
+            if let Err(error) = generate_default_config() {
+                print_error(&error);
+                exit(1);
+            }
+
+            return;
+        }
+        Command::CheckConfig => {
+            if !check_config() {
+                exit(1);
+            }
+
+            return;
+        }
+        _ => {}
     }
 
-    if args.batch {
-        let start = Instant::now();
-        let number_of_threads = args.threads.unwrap_or(3);
-        batch(number_of_threads, args.skip_lidar, args.skip_vector);
-        let duration = start.elapsed();
-        println!("Tiles generated in {:.1?}", duration);
+    let config = match get_config() {
+        Ok(config) => config,
+        Err(error) => {
+            print_error(&error);
+            exit(1);
+        }
+    };
+
+    let mut pipeline = Pipeline::new(config).force(args.force);
 
-        return;
+    if let Some(input_dir) = args.input_dir.as_deref() {
+        pipeline = pipeline.input_dir(input_dir);
     }
 
-    if let Some(file_name) = args.file_path.as_deref() {
-        let start = Instant::now();
-        let laz_path = Path::new(file_name);
-        let dir_path = Path::new("out").join("tile");
+    if let Some(output_dir) = args.output_dir.as_deref() {
+        pipeline = pipeline.output_dir(output_dir);
+    }
 
-        if !args.skip_lidar {
-            generate_dem_and_vegetation_density_tiff_images_from_laz_file(
-                &laz_path.to_path_buf(),
-                &dir_path,
-            );
-        }
+    let start = Instant::now();
+
+    let result = match args.command {
+        Command::Tile {
+            laz_path,
+            neighbors_dir,
+            skip_lidar,
+            skip_vector,
+        } => {
+            if let Some(neighbors_dir) = neighbors_dir {
+                pipeline = pipeline.neighbors_dir(neighbors_dir);
+            }
 
-        let mut file = File::open(&laz_path).expect("Cound not open laz file");
-        let header = Header::read_from(&mut file).unwrap();
-
-        let tile = Tile {
-            dir_path,
-            laz_path: laz_path.to_path_buf(),
-            min_x: header.min_x.round() as i64,
-            min_y: header.min_y.round() as i64,
-            max_x: header.max_x.round() as i64,
-            max_y: header.max_y.round() as i64,
-        };
-
-        let neighbor_tiles = NeighborTiles {
-            top: None,
-            top_right: None,
-            right: None,
-            bottom_right: None,
-            bottom: None,
-            bottom_left: None,
-            left: None,
-            top_left: None,
-        };
-
-        if !args.skip_vector {
-            download_osm_file_if_needed(tile.min_x, tile.min_y, tile.max_x, tile.max_y);
+            pipeline
+                .skip_lidar(skip_lidar)
+                .skip_vector(skip_vector)
+                .run_tile(Path::new(&laz_path))
+                .map(|_| println!("Tile generated in {:.1?}", start.elapsed()))
+        }
+        Command::Lidar { laz_path } => pipeline
+            .run_lidar(Path::new(&laz_path))
+            .map(|tile| println!("Rasters generated in {:?}", tile.dir_path)),
+        Command::Render {
+            tile_dir,
+            skip_vector,
+        } => pipeline
+            .skip_vector(skip_vector)
+            .run_render(Path::new(&tile_dir))
+            .map(|_| println!("Tile rendered in {:.1?}", start.elapsed())),
+        Command::Download { bbox, epsg_code } => {
+            pipeline.download_area(bbox[0], bbox[1], bbox[2], bbox[3], epsg_code)
+        }
+        Command::Merge { tile_dirs } => {
+            let dir_paths: Vec<PathBuf> = tile_dirs.iter().map(PathBuf::from).collect();
+            pipeline.run_merge(&dir_paths)
         }
+        Command::Batch(batch_args) => run_batch(pipeline, batch_args, start),
+        Command::Init | Command::CheckConfig => unreachable!(),
+    };
+
+    if let Err(error) = result {
+        print_error(&error);
+        exit(1);
+    }
+}
+
+fn run_batch(
+    mut pipeline: Pipeline,
+    batch_args: BatchArgs,
+    start: Instant,
+) -> Result<(), cassini::Error> {
+    pipeline = pipeline
+        .skip_lidar(batch_args.skip_lidar)
+        .skip_vector(batch_args.skip_vector);
+
+    if let Some(threads) = batch_args.threads {
+        pipeline = pipeline.threads(threads);
+    }
+
+    if let Some(lidar_threads) = batch_args.lidar_threads {
+        pipeline = pipeline.lidar_threads(lidar_threads);
+    }
+
+    if let Some(render_threads) = batch_args.render_threads {
+        pipeline = pipeline.render_threads(render_threads);
+    }
+
+    if let Some(report) = batch_args.report.as_deref() {
+        pipeline = pipeline.report_path(report);
+    }
+
+    let report = pipeline.run_batch()?;
+    println!("Tiles generated in {:.1?}", start.elapsed());
+
+    if report.has_failures() {
+        exit(1);
+    }
+
+    Ok(())
+}
 
-        generate_png_from_dem_vegetation_density_tiff_images_and_vector_file(
-            tile,
-            neighbor_tiles,
-            args.skip_vector,
-        );
+fn print_error(error: &dyn Error) {
+    eprintln!("Error: {}", error);
+    let mut source = error.source();
 
-        let duration = start.elapsed();
-        println!("Tile generated in {:.1?}", duration);
+    while let Some(cause) = source {
+        eprintln!("  Caused by: {}", cause);
+        source = cause.source();
     }
 }
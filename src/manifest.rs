@@ -0,0 +1,346 @@
+use crate::{
+    config::Config,
+    error::{file_error, Error, Stage},
+    tile::{NeighborTiles, Tile},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fs::{self, metadata},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+    time::UNIX_EPOCH,
+};
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+// The versions of the algorithm of every stage, part of their fingerprints. Bump the version of a
+// stage when a change of its code changes its outputs, so existing tiles get generated again.
+const LIDAR_VERSION: u32 = 1;
+const VEGETATION_VERSION: u32 = 1;
+const DEM_VERSION: u32 = 1;
+const CONTOURS_VERSION: u32 = 1;
+const CLIFFS_VERSION: u32 = 1;
+const TREES_VERSION: u32 = 1;
+const VECTORS_VERSION: u32 = 1;
+const FULL_MAP_VERSION: u32 = 1;
+
+/// Keeps track of the inputs each stage of a tile was last run with,
+/// so unchanged stages can be skipped when running cassini again.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tile: Option<RecordedTile>,
+    /// Mean number of LiDAR points per square meter, recorded by the LiDAR stage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    point_density: Option<f64>,
+    #[serde(default)]
+    stages: BTreeMap<String, String>,
+}
+
+/// A tile as stored in its manifest. Manifests written before CRSs were supported have no EPSG
+/// code.
+#[derive(Serialize, Deserialize)]
+struct RecordedTile {
+    laz_path: PathBuf,
+    min_x: i64,
+    min_y: i64,
+    max_x: i64,
+    max_y: i64,
+    #[serde(default)]
+    epsg_code: Option<u16>,
+}
+
+impl Manifest {
+    /// Loads the manifest of a tile directory. A missing or unreadable manifest is empty.
+    pub fn load(dir_path: &Path) -> Manifest {
+        fs::read_to_string(dir_path.join(MANIFEST_FILE_NAME))
+            .ok()
+            .and_then(|raw_manifest| serde_json::from_str(&raw_manifest).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, dir_path: &Path) -> Result<(), Error> {
+        fs::create_dir_all(dir_path).map_err(file_error(dir_path))?;
+        let manifest_path = dir_path.join(MANIFEST_FILE_NAME);
+        let json_string = serde_json::to_string_pretty(self).unwrap();
+        fs::write(&manifest_path, json_string).map_err(file_error(&manifest_path))
+    }
+
+    fn is_up_to_date(&self, stage: Stage, fingerprint: &str) -> bool {
+        self.stages.get(stage.key()).map(|value| value.as_str()) == Some(fingerprint)
+    }
+}
+
+/// Stores the LiDAR file and the bounds of a tile in its manifest, so the tile can later be
+/// rendered or merged from its directory alone.
+pub fn record_tile(tile: &Tile) -> Result<(), Error> {
+    let mut manifest = Manifest::load(&tile.dir_path);
+    manifest.tile = Some(RecordedTile {
+        laz_path: tile.laz_path.clone(),
+        min_x: tile.min_x,
+        min_y: tile.min_y,
+        max_x: tile.max_x,
+        max_y: tile.max_y,
+        epsg_code: Some(tile.epsg_code),
+    });
+    manifest.save(&tile.dir_path)
+}
+
+/// Reads back the tile stored in the manifest of a tile directory. The EPSG code of the config is
+/// used for tiles recorded without one.
+pub fn read_tile(dir_path: &Path, config_epsg_code: Option<u16>) -> Result<Tile, Error> {
+    let Some(tile) = Manifest::load(dir_path).tile else {
+        return Err(Error::NotATileDir(dir_path.to_path_buf()));
+    };
+
+    let epsg_code = tile
+        .epsg_code
+        .or(config_epsg_code)
+        .ok_or_else(|| Error::MissingTileCrs(dir_path.to_path_buf()))?;
+
+    Ok(Tile {
+        dir_path: dir_path.to_path_buf(),
+        laz_path: tile.laz_path,
+        min_x: tile.min_x,
+        min_y: tile.min_y,
+        max_x: tile.max_x,
+        max_y: tile.max_y,
+        epsg_code,
+    })
+}
+
+/// Stores the mean point density of the LiDAR file of a tile in its manifest.
+pub fn record_point_density(dir_path: &Path, point_density: f64) -> Result<(), Error> {
+    let mut manifest = Manifest::load(dir_path);
+    manifest.point_density = Some(point_density);
+    manifest.save(dir_path)
+}
+
+/// Reads back the mean point density stored in the manifest of a tile directory, if any.
+pub fn read_point_density(dir_path: &Path) -> Option<f64> {
+    Manifest::load(dir_path).point_density
+}
+
+/// Runs a tile stage unless the manifest shows it already ran with the same inputs
+/// and all its outputs still exist.
+pub fn run_stage_if_needed<F>(
+    dir_path: &Path,
+    stage: Stage,
+    fingerprint: &str,
+    outputs: &[PathBuf],
+    force: bool,
+    task: F,
+) -> Result<(), Error>
+where
+    F: FnOnce() -> Result<(), Error>,
+{
+    let mut manifest = Manifest::load(dir_path);
+
+    if !force
+        && manifest.is_up_to_date(stage, fingerprint)
+        && outputs.iter().all(|output| output.exists())
+    {
+        println!("Skipping {} for {:?}, already up to date", stage, dir_path);
+        return Ok(());
+    }
+
+    // Removing the entry first so an interrupted stage is run again on resume
+    if manifest.stages.remove(stage.key()).is_some() {
+        manifest.save(dir_path)?;
+    }
+
+    task()?;
+
+    manifest
+        .stages
+        .insert(stage.key().to_owned(), fingerprint.to_owned());
+    manifest.save(dir_path)
+}
+
+/// The fingerprints of every tile stage, each one including the fingerprints of the stages
+/// it depends on, so a change in an upstream stage invalidates its dependents.
+pub struct StageFingerprints {
+    pub vegetation: String,
+    pub dem: String,
+    pub contours: String,
+    pub cliffs: String,
+    pub trees: String,
+    pub vectors: Option<String>,
+    pub full_map: String,
+}
+
+impl StageFingerprints {
+    pub fn new(
+        tile: &Tile,
+        neighbor_tiles: &NeighborTiles,
+        skip_vector: bool,
+        config: &Config,
+    ) -> StageFingerprints {
+        let lidar_fingerprints: Vec<String> = [tile]
+            .into_iter()
+            .chain(neighbor_tiles)
+            .map(|tile| get_lidar_fingerprint(tile, config))
+            .collect();
+
+        let vegetation = get_fingerprint(&json!({
+            "version": VEGETATION_VERSION,
+            "lidar": lidar_fingerprints,
+            "yellow_threshold": config.yellow_threshold,
+            "green_threshold_1": config.green_threshold_1,
+            "green_threshold_2": config.green_threshold_2,
+            "green_threshold_3": config.green_threshold_3,
+            "yellow_band": config.lidar.yellow_band,
+            "green_band": config.lidar.green_band,
+            "undergrowth": config.undergrowth,
+            "dpi_resolution": config.dpi_resolution,
+        }));
+
+        let dem = get_fingerprint(&json!({
+            "version": DEM_VERSION,
+            "lidar": lidar_fingerprints,
+            "dem": config.dem,
+        }));
+
+        let contours = get_fingerprint(&json!({
+            "version": CONTOURS_VERSION,
+            "dem": dem,
+            "contours": config.contours,
+            "form_lines": config.form_lines,
+            "dpi_resolution": config.dpi_resolution,
+        }));
+
+        let cliffs = get_fingerprint(&json!({
+            "version": CLIFFS_VERSION,
+            "dem": dem,
+            "cliff_threshold_1": config.cliff_threshold_1,
+            "cliff_threshold_2": config.cliff_threshold_2,
+            "terrain": config.terrain,
+            "dpi_resolution": config.dpi_resolution,
+        }));
+
+        let trees = get_fingerprint(&json!({
+            "version": TREES_VERSION,
+            "lidar": lidar_fingerprints,
+            "trees": config.trees,
+            "dpi_resolution": config.dpi_resolution,
+        }));
+
+        let vectors = if skip_vector {
+            None
+        } else {
+            let osm_path = config
+                .input_dir
+                .join(format!("{:0>7}_{:0>7}.osm", tile.min_x, tile.max_y));
+
+            Some(get_fingerprint(&json!({
+                "version": VECTORS_VERSION,
+                "osm": get_file_state(&osm_path),
+                "epsg_code": tile.epsg_code,
+                "dpi_resolution": config.dpi_resolution,
+                "ogr2ogr": get_tool_versions().ogr2ogr,
+            })))
+        };
+
+        let full_map = get_fingerprint(&json!({
+            "version": FULL_MAP_VERSION,
+            "vegetation": vegetation,
+            "contours": contours,
+            "cliffs": cliffs,
+            "trees": trees,
+            "vectors": vectors,
+        }));
+
+        StageFingerprints {
+            vegetation,
+            dem,
+            contours,
+            cliffs,
+            trees,
+            vectors,
+            full_map,
+        }
+    }
+}
+
+pub fn get_lidar_fingerprint(tile: &Tile, config: &Config) -> String {
+    get_fingerprint(&json!({
+        "version": LIDAR_VERSION,
+        "laz": get_file_state(&tile.laz_path),
+        "bounds": [tile.min_x, tile.min_y, tile.max_x, tile.max_y],
+        "epsg_code": tile.epsg_code,
+        "lidar": config.lidar,
+    }))
+}
+
+pub fn get_lidar_outputs(tile: &Tile, config: &Config) -> Vec<PathBuf> {
+    let band_file_names = config
+        .lidar
+        .height_bands
+        .iter()
+        .map(|band| format!("{}.tif", band.name));
+
+    [
+        "dem.tif".to_owned(),
+        "dem-low-resolution.tif".to_owned(),
+        "point-density.tif".to_owned(),
+        "chm.tif".to_owned(),
+    ]
+    .into_iter()
+    .chain(band_file_names)
+    .map(|file_name| tile.dir_path.join(file_name))
+    .collect()
+}
+
+/// Size and modification date of a file, cheaper than hashing big LiDAR files.
+fn get_file_state(path: &Path) -> Value {
+    match metadata(path) {
+        Ok(file_metadata) => json!({
+            "path": path,
+            "size": file_metadata.len(),
+            "modified": file_metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs()),
+        }),
+        Err(_) => Value::Null,
+    }
+}
+
+/// FNV-1a hash of the JSON representation of the inputs, stable across runs and platforms.
+fn get_fingerprint(inputs: &Value) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in inputs.to_string().bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+/// The versions of the external programs run by the stages.
+struct ToolVersions {
+    /// Converts the OpenStreetMap data in the vectors stage
+    ogr2ogr: String,
+}
+
+fn get_tool_versions() -> &'static ToolVersions {
+    static TOOL_VERSIONS: OnceLock<ToolVersions> = OnceLock::new();
+
+    TOOL_VERSIONS.get_or_init(|| ToolVersions {
+        ogr2ogr: get_command_version("ogr2ogr"),
+    })
+}
+
+fn get_command_version(program: &str) -> String {
+    match Command::new(program).arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_owned()
+        }
+        _ => "unknown".to_owned(),
+    }
+}
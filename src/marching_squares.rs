@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An edge of the elevation matrix grid: the column and row of its first corner, and whether it
+/// goes east (`false`) or north (`true`) from it. Adjacent cells share the crossing points of
+/// their common edges, which is how segments are joined into polylines.
+type EdgeKey = (usize, usize, bool);
+
+/// Traces the contours of an elevation matrix indexed by `[x][y]`, with y going north, every
+/// `interval` meters. Cells with a NaN corner are skipped, so contours stop at missing data.
+///
+/// Every contour is returned as its x coordinates, y coordinates and elevation, the matrix value
+/// at `[i][j]` being located at the center of the cell `(x_start + i * size, y_start + j * size)`.
+/// Closed contours end with their first point, and all contours have the higher ground on their
+/// left.
+pub fn trace_contours(
+    elevations: &[Vec<f64>],
+    x_start: f64,
+    y_start: f64,
+    size: f64,
+    interval: f64,
+) -> Vec<(Vec<f64>, Vec<f64>, f64)> {
+    let mut contours: Vec<(Vec<f64>, Vec<f64>, f64)> = vec![];
+
+    let (min_elevation, max_elevation) = elevations
+        .iter()
+        .flatten()
+        .filter(|elevation| !elevation.is_nan())
+        .fold((f64::MAX, f64::MIN), |(min, max), elevation| {
+            (min.min(*elevation), max.max(*elevation))
+        });
+
+    if min_elevation > max_elevation {
+        return contours;
+    }
+
+    let first_level = (min_elevation / interval).ceil() as i64;
+    let last_level = (max_elevation / interval).floor() as i64;
+
+    for level in first_level..=last_level {
+        let elevation = level as f64 * interval;
+
+        for polyline in trace_level(elevations, elevation) {
+            let (x_array, y_array) = polyline
+                .into_iter()
+                .map(|(x, y)| (x_start + (x + 0.5) * size, y_start + (y + 0.5) * size))
+                .unzip();
+
+            contours.push((x_array, y_array, elevation));
+        }
+    }
+
+    contours
+}
+
+/// The polylines of a single elevation, in matrix coordinates.
+fn trace_level(elevations: &[Vec<f64>], elevation: f64) -> Vec<Vec<(f64, f64)>> {
+    let width = elevations.len();
+    let height = elevations.first().map_or(0, |column| column.len());
+    let is_above = |x: usize, y: usize| elevations[x][y] >= elevation;
+
+    // Segments go from the edge they enter the cell by to the edge they leave it by
+    let mut segments: BTreeMap<EdgeKey, EdgeKey> = BTreeMap::new();
+
+    for x in 0..width.saturating_sub(1) {
+        for y in 0..height.saturating_sub(1) {
+            // Corners and edges in counterclockwise order, starting from the south west corner
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let edges: [EdgeKey; 4] = [
+                (x, y, false),
+                (x + 1, y, true),
+                (x, y + 1, false),
+                (x, y, true),
+            ];
+
+            if corners
+                .iter()
+                .any(|&(corner_x, corner_y)| elevations[corner_x][corner_y].is_nan())
+            {
+                continue;
+            }
+
+            let above = corners.map(|(corner_x, corner_y)| is_above(corner_x, corner_y));
+
+            if above
+                .iter()
+                .all(|is_corner_above| *is_corner_above == above[0])
+            {
+                continue;
+            }
+
+            // Going counterclockwise, rising crossings go from below to above the elevation
+            // and falling ones from above to below
+            let mut rising: Vec<usize> = vec![];
+            let mut falling: Vec<usize> = vec![];
+
+            for index in 0..4 {
+                match (above[index], above[(index + 1) % 4]) {
+                    (false, true) => rising.push(index),
+                    (true, false) => falling.push(index),
+                    _ => {}
+                }
+            }
+
+            // Going from a falling crossing to a rising one keeps the higher ground on the left.
+            // On saddles, the mean of the corners decides if the higher corners are connected, a
+            // falling crossing then going to the next rising one instead of the previous one.
+            let is_center_above = corners
+                .iter()
+                .map(|&(corner_x, corner_y)| elevations[corner_x][corner_y])
+                .sum::<f64>()
+                / 4.
+                >= elevation;
+
+            for &falling_index in &falling {
+                let rising_by_distance = rising
+                    .iter()
+                    .map(|&rising_index| ((rising_index + 4 - falling_index) % 4, rising_index));
+
+                let rising_index = if is_center_above {
+                    rising_by_distance.min()
+                } else {
+                    rising_by_distance.max()
+                };
+
+                if let Some((_, rising_index)) = rising_index {
+                    segments.insert(edges[falling_index], edges[rising_index]);
+                }
+            }
+        }
+    }
+
+    let get_point = |(x, y, is_north): EdgeKey| {
+        let (next_x, next_y) = if is_north { (x, y + 1) } else { (x + 1, y) };
+        let start_elevation = elevations[x][y];
+        let ratio = (elevation - start_elevation) / (elevations[next_x][next_y] - start_elevation);
+
+        if is_north {
+            (x as f64, y as f64 + ratio)
+        } else {
+            (x as f64 + ratio, y as f64)
+        }
+    };
+
+    let ends: BTreeSet<EdgeKey> = segments.values().copied().collect();
+    let open_starts: Vec<EdgeKey> = segments
+        .keys()
+        .filter(|start| !ends.contains(start))
+        .copied()
+        .collect();
+
+    let mut chains: Vec<Vec<EdgeKey>> = vec![];
+
+    // Open polylines first, starting where no segment ends, then the remaining closed ones
+    for start in open_starts {
+        chains.push(follow_segments(&mut segments, start));
+    }
+
+    while let Some(&start) = segments.keys().next() {
+        chains.push(follow_segments(&mut segments, start));
+    }
+
+    chains
+        .into_iter()
+        .map(|chain| chain.into_iter().map(get_point).collect())
+        .collect()
+}
+
+/// Removes the segments of a polyline from the map, returning the edges it goes through.
+fn follow_segments(segments: &mut BTreeMap<EdgeKey, EdgeKey>, start: EdgeKey) -> Vec<EdgeKey> {
+    let mut chain = vec![start];
+    let mut edge = start;
+
+    while let Some(next_edge) = segments.remove(&edge) {
+        chain.push(next_edge);
+        edge = next_edge;
+    }
+
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_elevations(
+        width: usize,
+        height: usize,
+        elevation: impl Fn(usize, usize) -> f64,
+    ) -> Vec<Vec<f64>> {
+        (0..width)
+            .map(|x| (0..height).map(|y| elevation(x, y)).collect())
+            .collect()
+    }
+
+    /// The sides of a 2 by 2 matrix a polyline goes through.
+    fn get_sides(polyline: &[(f64, f64)]) -> Vec<&'static str> {
+        polyline
+            .iter()
+            .map(|&(x, y)| {
+                if y == 0. {
+                    "south"
+                } else if x == 1. {
+                    "east"
+                } else if y == 1. {
+                    "north"
+                } else {
+                    "west"
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn connects_the_higher_corners_of_a_saddle_above_the_elevation() {
+        // The mean of the corners is the elevation
+        let elevations = vec![vec![10., 0.], vec![0., 10.]];
+        let mut polylines: Vec<Vec<&str>> = trace_level(&elevations, 5.)
+            .iter()
+            .map(|polyline| get_sides(polyline))
+            .collect();
+        polylines.sort();
+
+        // The lower corners are cut off, with the higher ground on the left
+        assert_eq!(
+            polylines,
+            vec![vec!["north", "west"], vec!["south", "east"]]
+        );
+    }
+
+    #[test]
+    fn connects_the_lower_corners_of_a_saddle_below_the_elevation() {
+        let elevations = vec![vec![9., 0.], vec![0., 9.]];
+        let mut polylines: Vec<Vec<&str>> = trace_level(&elevations, 5.)
+            .iter()
+            .map(|polyline| get_sides(polyline))
+            .collect();
+        polylines.sort();
+
+        // The higher corners are cut off, with the higher ground on the left
+        assert_eq!(
+            polylines,
+            vec![vec!["north", "east"], vec!["south", "west"]]
+        );
+    }
+
+    #[test]
+    fn closes_rings_with_their_first_point() {
+        let elevations = create_elevations(5, 5, |x, y| if (x, y) == (2, 2) { 10. } else { 0. });
+        let polylines = trace_level(&elevations, 5.);
+
+        assert_eq!(polylines.len(), 1);
+        let ring = &polylines[0];
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring.first(), ring.last());
+
+        // Counterclockwise around the summit, which is on the left
+        let double_area: f64 = ring
+            .windows(2)
+            .map(|points| points[0].0 * points[1].1 - points[1].0 * points[0].1)
+            .sum();
+        assert!(double_area > 0.);
+    }
+
+    #[test]
+    fn cuts_open_polylines_at_the_border() {
+        // Rising towards the east
+        let elevations = create_elevations(3, 4, |x, _| x as f64);
+        let polylines = trace_level(&elevations, 0.5);
+
+        assert_eq!(polylines.len(), 1);
+        let polyline = &polylines[0];
+        assert!(polyline.iter().all(|&(x, _)| x == 0.5));
+        // Going south, with the higher ground on the left
+        assert_eq!(polyline.first(), Some(&(0.5, 3.)));
+        assert_eq!(polyline.last(), Some(&(0.5, 0.)));
+    }
+
+    #[test]
+    fn skips_the_cells_with_a_nan_corner() {
+        let elevations =
+            create_elevations(
+                4,
+                4,
+                |x, y| {
+                    if (x, y) == (1, 1) {
+                        f64::NAN
+                    } else {
+                        x as f64
+                    }
+                },
+            );
+        let polylines = trace_level(&elevations, 1.5);
+
+        // The cells around the NaN corner are skipped, so the contour stops at them
+        assert_eq!(polylines, vec![vec![(1.5, 3.), (1.5, 2.)]]);
+
+        let contours = trace_contours(&elevations, 1000., 2000., 2., 1.);
+        let elevations: Vec<f64> = contours.iter().map(|contour| contour.2).collect();
+        assert_eq!(elevations, vec![1., 2., 3.]);
+    }
+
+    #[test]
+    fn goes_through_the_corners_at_the_elevation() {
+        let elevations = create_elevations(3, 3, |x, _| x as f64);
+        let polylines = trace_level(&elevations, 1.);
+
+        // The corners at the elevation count as above it, so they are only crossed once
+        assert_eq!(polylines.len(), 1);
+        assert_eq!(polylines[0], vec![(1., 2.), (1., 1.), (1., 0.)]);
+    }
+
+    #[test]
+    fn places_the_contours_at_the_cell_centers() {
+        let elevations = create_elevations(3, 2, |x, _| x as f64);
+        let contours = trace_contours(&elevations, 1000., 2000., 2., 1.);
+
+        assert_eq!(
+            contours,
+            vec![
+                (vec![1003., 1003.], vec![2003., 2001.], 1.),
+                (vec![1005., 1005.], vec![2003., 2001.], 2.),
+            ]
+        );
+    }
+}
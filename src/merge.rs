@@ -1,15 +1,16 @@
-use std::path::Path;
-
-use crate::{canvas::Canvas, config::get_config, constants::INCH, tile::TileWithNeighbors};
-
-pub fn merge_maps(tiles_with_neighbors: Vec<TileWithNeighbors>) {
+use crate::{
+    canvas::Canvas, config::Config, constants::INCH, error::Error, tile::TileWithNeighbors,
+};
+
+pub fn merge_maps(
+    tiles_with_neighbors: Vec<TileWithNeighbors>,
+    config: &Config,
+) -> Result<(), Error> {
     println!("Merging maps");
 
-    let config = get_config();
-
     if tiles_with_neighbors.len() == 0 {
         println!("No map to merge.");
-        return;
+        return Ok(());
     }
 
     let first_tile = &tiles_with_neighbors[0].tile;
@@ -39,7 +40,7 @@ pub fn merge_maps(tiles_with_neighbors: Vec<TileWithNeighbors>) {
     let mut merge_image = Canvas::new(merge_image_width as i32, merge_image_height as i32);
 
     for tile in tiles_with_neighbors {
-        let mut map = Canvas::load_from(tile.tile.dir_path.join("full-map.png").to_str().unwrap());
+        let mut map = Canvas::load_from(&tile.tile.dir_path.join("full-map.png"))?;
 
         merge_image.overlay(
             &mut map,
@@ -49,5 +50,5 @@ pub fn merge_maps(tiles_with_neighbors: Vec<TileWithNeighbors>) {
         )
     }
 
-    merge_image.save_as(Path::new("out").join("merged-map.png").to_str().unwrap())
+    merge_image.save_as(&config.output_dir.join("merged-map.png"))
 }
@@ -0,0 +1,343 @@
+use crate::{
+    batch::{batch, check_same_crs, get_tiles, get_tiles_map, get_tiles_with_neighbors, LidarFile},
+    config::Config,
+    constants::BUFFER,
+    crs::get_epsg_code,
+    download::download_osm_file_if_needed,
+    error::{las_error, Error, Stage, StageResultExt},
+    lidar::generate_tile_rasters_if_needed,
+    manifest::{get_lidar_outputs, read_tile},
+    merge::merge_maps,
+    png::generate_png_from_dem_vegetation_density_tiff_images_and_vector_file,
+    report::BatchReport,
+    tile::{NeighborTiles, Tile, TileWithNeighbors},
+    tile_index::TileIndex,
+};
+use las::{Read, Reader};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_NUMBER_OF_THREADS: usize = 3;
+
+/// Entry point of the map generation pipeline.
+///
+/// ```no_run
+/// use cassini::{Config, Pipeline};
+///
+/// let config: Config = serde_json::from_str("{}").unwrap();
+///
+/// Pipeline::new(config)
+///     .input_dir("my-project/in")
+///     .output_dir("my-project/out")
+///     .threads(6)
+///     .run_batch()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Pipeline {
+    config: Config,
+    lidar_threads: usize,
+    render_threads: usize,
+    skip_lidar: bool,
+    skip_vector: bool,
+    force: bool,
+    neighbors_dir: Option<PathBuf>,
+    report_path: Option<PathBuf>,
+}
+
+impl Pipeline {
+    pub fn new(config: Config) -> Pipeline {
+        Pipeline {
+            config,
+            lidar_threads: DEFAULT_NUMBER_OF_THREADS,
+            render_threads: DEFAULT_NUMBER_OF_THREADS,
+            skip_lidar: false,
+            skip_vector: false,
+            force: false,
+            neighbors_dir: None,
+            report_path: None,
+        }
+    }
+
+    pub fn input_dir(mut self, input_dir: impl Into<PathBuf>) -> Pipeline {
+        self.config.input_dir = input_dir.into();
+        self
+    }
+
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Pipeline {
+        self.config.output_dir = output_dir.into();
+        self
+    }
+
+    /// Sets the number of threads of both the LiDAR and the rendering stages.
+    pub fn threads(mut self, threads: usize) -> Pipeline {
+        self.lidar_threads = threads;
+        self.render_threads = threads;
+        self
+    }
+
+    /// Sets the number of tiles processed concurrently by the memory heavy LiDAR stage.
+    pub fn lidar_threads(mut self, lidar_threads: usize) -> Pipeline {
+        self.lidar_threads = lidar_threads;
+        self
+    }
+
+    /// Sets the number of tiles rendered concurrently.
+    pub fn render_threads(mut self, render_threads: usize) -> Pipeline {
+        self.render_threads = render_threads;
+        self
+    }
+
+    pub fn skip_lidar(mut self, skip_lidar: bool) -> Pipeline {
+        self.skip_lidar = skip_lidar;
+        self
+    }
+
+    pub fn skip_vector(mut self, skip_vector: bool) -> Pipeline {
+        self.skip_vector = skip_vector;
+        self
+    }
+
+    /// Runs every stage again, even the ones the tile manifests show as up to date.
+    pub fn force(mut self, force: bool) -> Pipeline {
+        self.force = force;
+        self
+    }
+
+    /// Directory where `run_tile` looks for the neighbors of the tile, instead of the
+    /// directory of its LiDAR file.
+    pub fn neighbors_dir(mut self, neighbors_dir: impl Into<PathBuf>) -> Pipeline {
+        self.neighbors_dir = Some(neighbors_dir.into());
+        self
+    }
+
+    /// Writes the batch report as JSON to this path at the end of `run_batch`.
+    pub fn report_path(mut self, report_path: impl Into<PathBuf>) -> Pipeline {
+        self.report_path = Some(report_path.into());
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Processes every LiDAR file of the input directory and merges the resulting maps.
+    /// A tile failing does not stop the others, failures are listed in the returned report.
+    /// Stages already run with the same inputs are skipped, so an interrupted batch resumes.
+    pub fn run_batch(&self) -> Result<BatchReport, Error> {
+        let report = batch(
+            self.lidar_threads,
+            self.render_threads,
+            self.skip_lidar,
+            self.skip_vector,
+            self.force,
+            &self.config,
+        )?;
+
+        report.print();
+
+        if let Some(report_path) = &self.report_path {
+            report.write_json(report_path)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Generates the map for a single LiDAR file, in the "tile" subdirectory of the output directory.
+    /// The LiDAR files next to it (or in the neighbors directory) covering the adjacent tiles
+    /// are processed too, so the map has no artifacts on its edges.
+    pub fn run_tile(&self, laz_path: &Path) -> Result<Tile, Error> {
+        let tile = self.tile_from_laz_file(laz_path, self.config.output_dir.join("tile"))?;
+        let mut neighbor_tiles = self.neighbor_tiles(&tile)?;
+
+        if !self.skip_lidar {
+            self.lidar(&tile)?;
+        }
+
+        neighbor_tiles.retain(|neighbor_tile| {
+            if self.skip_lidar {
+                return get_lidar_outputs(neighbor_tile, &self.config)
+                    .iter()
+                    .all(|path| path.exists());
+            }
+
+            println!(
+                "Generating raw rasters for neighbor {:?}",
+                neighbor_tile.laz_path
+            );
+
+            // A broken neighbor only degrades the edges of the map, it should not prevent rendering it
+            match self.lidar(neighbor_tile) {
+                Ok(()) => true,
+                Err(error) => {
+                    eprintln!(
+                        "Warning: leaving out neighbor {:?}: {}",
+                        neighbor_tile.laz_path,
+                        error.full_message()
+                    );
+                    false
+                }
+            }
+        });
+
+        if !self.skip_vector {
+            self.download(&tile)?;
+        }
+
+        self.render(&tile, &neighbor_tiles)?;
+
+        Ok(tile)
+    }
+
+    /// Generates the rasters of a single LiDAR file, in the "tile" subdirectory of the output directory.
+    pub fn run_lidar(&self, laz_path: &Path) -> Result<Tile, Error> {
+        let tile = self.tile_from_laz_file(laz_path, self.config.output_dir.join("tile"))?;
+        self.lidar(&tile)?;
+
+        Ok(tile)
+    }
+
+    /// Renders the map of a tile directory whose rasters were generated by `run_lidar`.
+    pub fn run_render(&self, dir_path: &Path) -> Result<Tile, Error> {
+        let tile = self.tile_from_dir(dir_path)?;
+
+        if !self.skip_vector {
+            self.download(&tile)?;
+        }
+
+        self.render(&tile, &NeighborTiles::default())?;
+
+        Ok(tile)
+    }
+
+    /// Merges the maps rendered in several tile directories.
+    pub fn run_merge(&self, dir_paths: &[PathBuf]) -> Result<(), Error> {
+        let tiles = dir_paths
+            .iter()
+            .map(|dir_path| {
+                Ok(TileWithNeighbors {
+                    tile: self.tile_from_dir(dir_path)?,
+                    neighbors: NeighborTiles::default(),
+                })
+            })
+            .collect::<Result<Vec<TileWithNeighbors>, Error>>()?;
+
+        self.merge(tiles)
+    }
+
+    /// Lists the LiDAR files of the input directory with their neighbors.
+    pub fn tiles(&self) -> Result<Vec<TileWithNeighbors>, Error> {
+        get_tiles_with_neighbors(
+            &self.config.input_dir,
+            &self.config.output_dir,
+            self.config.epsg_code,
+        )
+        .map(|(tiles, _)| tiles)
+        .in_stage(Stage::TilesDiscovery)
+    }
+
+    pub fn tile_from_laz_file(&self, laz_path: &Path, dir_path: PathBuf) -> Result<Tile, Error> {
+        let reader = Reader::from_path(laz_path).map_err(las_error(laz_path))?;
+        let header = reader.header();
+        let bounds = header.bounds();
+
+        Ok(Tile {
+            dir_path,
+            laz_path: laz_path.to_path_buf(),
+            min_x: bounds.min.x.round() as i64,
+            min_y: bounds.min.y.round() as i64,
+            max_x: bounds.max.x.round() as i64,
+            max_y: bounds.max.y.round() as i64,
+            epsg_code: get_epsg_code(laz_path, header, self.config.epsg_code)?,
+        })
+    }
+
+    /// Finds the neighbors of a tile among the LiDAR files of the neighbors directory,
+    /// defaulting to the directory of the tile LiDAR file.
+    pub fn neighbor_tiles(&self, tile: &Tile) -> Result<NeighborTiles, Error> {
+        let neighbors_dir = match &self.neighbors_dir {
+            Some(neighbors_dir) => neighbors_dir.clone(),
+            None => match tile.laz_path.parent() {
+                Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        };
+
+        let (tiles_map, _) = get_tiles_map(&neighbors_dir, self.config.epsg_code)
+            .and_then(|(tiles_map, skipped_files)| {
+                let tile_file = LidarFile {
+                    path: tile.laz_path.clone(),
+                    epsg_code: tile.epsg_code,
+                };
+
+                check_same_crs([&tile_file].into_iter().chain(tiles_map.values()))?;
+                Ok((tiles_map, skipped_files))
+            })
+            .in_stage(Stage::TilesDiscovery)?;
+
+        let tile_index = TileIndex::new(get_tiles(&tiles_map, &self.config.output_dir));
+
+        Ok(tile_index.get_neighbor_tiles(tile, BUFFER as i64))
+    }
+
+    /// Reads the tile of a directory in which the LiDAR stage already ran.
+    pub fn tile_from_dir(&self, dir_path: &Path) -> Result<Tile, Error> {
+        read_tile(dir_path, self.config.epsg_code)
+    }
+
+    /// Generates the DEM and vegetation density rasters of a tile.
+    pub fn lidar(&self, tile: &Tile) -> Result<(), Error> {
+        generate_tile_rasters_if_needed(tile, self.force, &self.config).for_tile(Stage::Lidar, tile)
+    }
+
+    /// Downloads the osm file covering a tile if it is not already in the input directory.
+    pub fn download(&self, tile: &Tile) -> Result<(), Error> {
+        self.download_area(
+            tile.min_x,
+            tile.min_y,
+            tile.max_x,
+            tile.max_y,
+            Some(tile.epsg_code),
+        )
+        .for_tile(Stage::Download, tile)
+    }
+
+    /// Downloads the osm file covering an area to the input directory. The coordinates are in the
+    /// given CRS, defaulting to the one of the config.
+    pub fn download_area(
+        &self,
+        min_x: i64,
+        min_y: i64,
+        max_x: i64,
+        max_y: i64,
+        epsg_code: Option<u16>,
+    ) -> Result<(), Error> {
+        let epsg_code = epsg_code
+            .or(self.config.epsg_code)
+            .ok_or(Error::UnknownCrs)?;
+
+        download_osm_file_if_needed(
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            epsg_code,
+            &self.config.input_dir,
+        )
+    }
+
+    /// Renders the full map of a tile from its rasters and its neighbors' ones.
+    pub fn render(&self, tile: &Tile, neighbor_tiles: &NeighborTiles) -> Result<(), Error> {
+        generate_png_from_dem_vegetation_density_tiff_images_and_vector_file(
+            tile.clone(),
+            neighbor_tiles.clone(),
+            self.skip_vector,
+            self.force,
+            &self.config,
+        )
+    }
+
+    /// Merges the rendered maps of several tiles into the output directory.
+    pub fn merge(&self, tiles: Vec<TileWithNeighbors>) -> Result<(), Error> {
+        merge_maps(tiles, &self.config).in_stage(Stage::Merge)
+    }
+}
@@ -1,32 +1,121 @@
+use crate::constants::INCH;
 use crate::contours::generate_contours_with_pullautin_algorithme;
+use crate::error::{Error, Stage, StageResultExt};
+use crate::manifest::{run_stage_if_needed, StageFingerprints};
 use crate::vectors::render_osm_vector_shapes;
-use crate::INCH;
 use crate::{
     cliffs::render_cliffs,
-    config::get_config,
-    dem::create_dem_with_buffer_and_slopes_tiff,
+    config::Config,
+    dem::create_dem_with_buffer,
     full_map::render_full_map_to_png,
     tile::{NeighborTiles, Tile},
+    trees::render_trees,
     vegetation::render_vegetation,
 };
 
+/// Renders every layer of a tile then the full map. Layers already rendered with the same
+/// inputs in a previous run are skipped, unless `force` is set.
 pub fn generate_png_from_dem_vegetation_density_tiff_images_and_vector_file(
     tile: Tile,
     neighbor_tiles: NeighborTiles,
     skip_vector: bool,
-) {
-    let config = get_config();
+    force: bool,
+    config: &Config,
+) -> Result<(), Error> {
     let image_width = ((tile.max_x - tile.min_x) as f32 * config.dpi_resolution / INCH) as u32;
     let image_height = ((tile.max_y - tile.min_y) as f32 * config.dpi_resolution / INCH) as u32;
+    let fingerprints = StageFingerprints::new(&tile, &neighbor_tiles, skip_vector, config);
+    let output = |file_name: &str| tile.dir_path.join(file_name);
 
-    render_vegetation(&tile, &neighbor_tiles, image_width, image_height, &config);
-    create_dem_with_buffer_and_slopes_tiff(&tile, &neighbor_tiles);
-    generate_contours_with_pullautin_algorithme(&tile, image_width, image_height, &config);
-    render_cliffs(&tile, image_width, image_height, &config);
+    let has_undergrowth = config.undergrowth.band.is_some();
+    let mut vegetation_outputs = vec![output("vegetation.png")];
 
-    if !skip_vector {
-        render_osm_vector_shapes(&tile, image_width, image_height, &config);
+    if has_undergrowth {
+        vegetation_outputs.push(output("undergrowth.png"));
     }
 
-    render_full_map_to_png(&tile, image_width, image_height, skip_vector);
+    run_stage_if_needed(
+        &tile.dir_path,
+        Stage::Vegetation,
+        &fingerprints.vegetation,
+        &vegetation_outputs,
+        force,
+        || render_vegetation(&tile, &neighbor_tiles, image_width, image_height, config),
+    )
+    .for_tile(Stage::Vegetation, &tile)?;
+
+    run_stage_if_needed(
+        &tile.dir_path,
+        Stage::Dem,
+        &fingerprints.dem,
+        &[
+            output("dem-with-buffer.tif"),
+            output("dem-with-buffer-interpolated.tif"),
+            output("dem-low-resolution-with-buffer.tif"),
+            output("dem-low-resolution-with-buffer-interpolated.tif"),
+        ],
+        force,
+        || create_dem_with_buffer(&tile, &neighbor_tiles, config),
+    )
+    .for_tile(Stage::Dem, &tile)?;
+
+    run_stage_if_needed(
+        &tile.dir_path,
+        Stage::Contours,
+        &fingerprints.contours,
+        &[output("contours.png")],
+        force,
+        || generate_contours_with_pullautin_algorithme(&tile, image_width, image_height, config),
+    )
+    .for_tile(Stage::Contours, &tile)?;
+
+    run_stage_if_needed(
+        &tile.dir_path,
+        Stage::Cliffs,
+        &fingerprints.cliffs,
+        &[output("cliffs.png")],
+        force,
+        || render_cliffs(&tile, image_width, image_height, config),
+    )
+    .for_tile(Stage::Cliffs, &tile)?;
+
+    run_stage_if_needed(
+        &tile.dir_path,
+        Stage::Trees,
+        &fingerprints.trees,
+        &[output("trees.png"), output("trees.json")],
+        force,
+        || render_trees(&tile, &neighbor_tiles, image_width, image_height, config),
+    )
+    .for_tile(Stage::Trees, &tile)?;
+
+    if let Some(vectors_fingerprint) = &fingerprints.vectors {
+        run_stage_if_needed(
+            &tile.dir_path,
+            Stage::Vectors,
+            vectors_fingerprint,
+            &[output("vectors.png")],
+            force,
+            || render_osm_vector_shapes(&tile, image_width, image_height, config),
+        )
+        .for_tile(Stage::Vectors, &tile)?;
+    }
+
+    run_stage_if_needed(
+        &tile.dir_path,
+        Stage::FullMap,
+        &fingerprints.full_map,
+        &[output("full-map.png")],
+        force,
+        || {
+            render_full_map_to_png(
+                &tile,
+                image_width,
+                image_height,
+                has_undergrowth,
+                skip_vector,
+            )
+        },
+    )
+    .for_tile(Stage::FullMap, &tile)
 }
@@ -0,0 +1,242 @@
+use crate::error::{file_error, las_error, Error};
+use las::{Header, Point, Read, Reader};
+use std::fs::File;
+use std::io::{Read as IoRead, Seek, SeekFrom};
+use std::path::Path;
+
+const LIDAR_FILE_EXTENSIONS: [&str; 2] = ["las", "laz"];
+const COPC_USER_ID: &str = "copc";
+const COPC_INFO_RECORD_ID: u16 = 1;
+const COPC_INFO_SIZE: usize = 56;
+const COPC_HIERARCHY_ENTRY_SIZE: usize = 32;
+
+/// A rectangular area, in the coordinates of the LiDAR files.
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Extent {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+
+    pub fn intersects(&self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> bool {
+        min_x <= self.max_x && max_x >= self.min_x && min_y <= self.max_y && max_y >= self.min_y
+    }
+}
+
+/// Whether the path has a LAS, LAZ or COPC extension, whatever its case.
+pub fn is_lidar_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            LIDAR_FILE_EXTENSIONS
+                .iter()
+                .any(|lidar_extension| extension.eq_ignore_ascii_case(lidar_extension))
+        })
+}
+
+/// Calls the given function on every point of a LiDAR file lying within the extent.
+/// For COPC files, only the octree nodes intersecting the extent are decompressed.
+pub fn for_each_point_in_extent<F>(path: &Path, extent: &Extent, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(Point),
+{
+    let mut reader = Reader::from_path(path).map_err(las_error(path))?;
+
+    let mut handle_point = |point: las::Result<Point>| -> Result<(), Error> {
+        let point = point.map_err(las_error(path))?;
+
+        if extent.contains(point.x, point.y) {
+            f(point);
+        }
+
+        Ok(())
+    };
+
+    match get_copc_point_ranges(path, reader.header(), extent)? {
+        Some(point_ranges) => {
+            for (first_point, number_of_points) in point_ranges {
+                reader.seek(first_point).map_err(las_error(path))?;
+
+                for point in reader.points().take(number_of_points as usize) {
+                    handle_point(point)?;
+                }
+            }
+        }
+        None => {
+            for point in reader.points() {
+                handle_point(point)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct CopcInfo {
+    center_x: f64,
+    center_y: f64,
+    halfsize: f64,
+    root_hierarchy_offset: u64,
+    root_hierarchy_size: u64,
+}
+
+impl CopcInfo {
+    /// Bounds of an octree node, the root node being the cube of the COPC info.
+    fn intersects(&self, entry: &HierarchyEntry, extent: &Extent) -> bool {
+        let size = 2. * self.halfsize / 2_f64.powi(entry.level);
+        let min_x = self.center_x - self.halfsize + entry.x as f64 * size;
+        let min_y = self.center_y - self.halfsize + entry.y as f64 * size;
+
+        extent.intersects(min_x, min_y, min_x + size, min_y + size)
+    }
+}
+
+struct HierarchyEntry {
+    level: i32,
+    x: i32,
+    y: i32,
+    offset: u64,
+    byte_size: i32,
+    point_count: i32,
+}
+
+impl HierarchyEntry {
+    fn from_bytes(bytes: &[u8]) -> HierarchyEntry {
+        HierarchyEntry {
+            level: read_i32(bytes, 0),
+            x: read_i32(bytes, 4),
+            y: read_i32(bytes, 8),
+            offset: read_u64(bytes, 16),
+            byte_size: read_i32(bytes, 24),
+            point_count: read_i32(bytes, 28),
+        }
+    }
+}
+
+/// The ranges of point indexes (first point and number of points) of the COPC octree nodes
+/// intersecting the extent. Returns None if the file is not a COPC file.
+fn get_copc_point_ranges(
+    path: &Path,
+    header: &Header,
+    extent: &Extent,
+) -> Result<Option<Vec<(u64, u64)>>, Error> {
+    let Some(info) = read_copc_info(path, header)? else {
+        return Ok(None);
+    };
+
+    let mut file = File::open(path).map_err(file_error(path))?;
+    let mut pages = vec![(info.root_hierarchy_offset, info.root_hierarchy_size)];
+    let mut nodes: Vec<(HierarchyEntry, bool)> = vec![];
+
+    // The whole hierarchy is needed even outside of the extent, as the points of every node
+    // shift the indexes of the following ones
+    while let Some((offset, size)) = pages.pop() {
+        let mut page = vec![0; size as usize];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut page))
+            .map_err(file_error(path))?;
+
+        if !page.len().is_multiple_of(COPC_HIERARCHY_ENTRY_SIZE) {
+            return Err(Error::InvalidCopc {
+                path: path.to_path_buf(),
+                reason: "truncated hierarchy page",
+            });
+        }
+
+        for entry in page
+            .chunks_exact(COPC_HIERARCHY_ENTRY_SIZE)
+            .map(HierarchyEntry::from_bytes)
+        {
+            // A point count of -1 means the entry points to a child hierarchy page
+            if entry.point_count == -1 {
+                pages.push((entry.offset, entry.byte_size as u64));
+            } else if entry.point_count > 0 {
+                let intersects = info.intersects(&entry, extent);
+                nodes.push((entry, intersects));
+            }
+        }
+    }
+
+    let number_of_points: u64 = nodes
+        .iter()
+        .map(|(entry, _)| entry.point_count as u64)
+        .sum();
+
+    if number_of_points != header.number_of_points() {
+        return Err(Error::InvalidCopc {
+            path: path.to_path_buf(),
+            reason: "the hierarchy does not match the number of points",
+        });
+    }
+
+    Ok(Some(get_point_ranges(nodes)))
+}
+
+/// Point data chunks are stored in the order of their offsets, which gives the index of the
+/// first point of every node. Consecutive nodes are merged to avoid seeking.
+fn get_point_ranges(mut nodes: Vec<(HierarchyEntry, bool)>) -> Vec<(u64, u64)> {
+    nodes.sort_by_key(|(entry, _)| entry.offset);
+
+    let mut point_ranges: Vec<(u64, u64)> = vec![];
+    let mut first_point: u64 = 0;
+
+    for (entry, intersects) in nodes {
+        let number_of_points = entry.point_count as u64;
+
+        if intersects {
+            match point_ranges.last_mut() {
+                Some((start, count)) if *start + *count == first_point => {
+                    *count += number_of_points
+                }
+                _ => point_ranges.push((first_point, number_of_points)),
+            }
+        }
+
+        first_point += number_of_points;
+    }
+
+    point_ranges
+}
+
+fn read_copc_info(path: &Path, header: &Header) -> Result<Option<CopcInfo>, Error> {
+    let Some(vlr) = header
+        .vlrs()
+        .iter()
+        .find(|vlr| vlr.user_id == COPC_USER_ID && vlr.record_id == COPC_INFO_RECORD_ID)
+    else {
+        return Ok(None);
+    };
+
+    if vlr.data.len() < COPC_INFO_SIZE {
+        return Err(Error::InvalidCopc {
+            path: path.to_path_buf(),
+            reason: "truncated info record",
+        });
+    }
+
+    Ok(Some(CopcInfo {
+        center_x: read_f64(&vlr.data, 0),
+        center_y: read_f64(&vlr.data, 8),
+        halfsize: read_f64(&vlr.data, 24),
+        root_hierarchy_offset: read_u64(&vlr.data, 40),
+        root_hierarchy_size: read_u64(&vlr.data, 48),
+    }))
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_f64(bytes: &[u8], offset: usize) -> f64 {
+    f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
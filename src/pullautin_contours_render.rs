@@ -1,10 +1,10 @@
 use image::RgbaImage;
 use imageproc::drawing::draw_line_segment_mut;
-use shapefile::dbase::{FieldIOError, FieldWriter, WritableRecord};
+use shapefile::dbase::{FieldIOError, FieldWriter, TableWriterBuilder, WritableRecord};
 use shapefile::record::polyline::GenericPolyline;
-use shapefile::{Point, Reader, Writer};
+use shapefile::{Point, Writer};
 use std::fs::{create_dir_all, File};
-use std::io::{stdout, BufReader, Write};
+use std::io::{stdout, Write};
 use std::time::Instant;
 
 struct FormLineRecord {
@@ -25,6 +25,8 @@ impl WritableRecord for FormLineRecord {
 
 use crate::config::Config;
 use crate::constants::{BUFFER, INCH};
+use crate::error::{file_error, image_error, shapefile_error, Error};
+use crate::pullautin_smooth_contours::Contour;
 use crate::{
     constants::{BROWN, TRANSPARENT},
     tile::Tile,
@@ -36,22 +38,22 @@ pub fn pullautin_cull_formlines_render_contours(
     image_height: u32,
     config: &Config,
     avg_alt: Vec<Vec<f64>>,
-    smoothed_contours: Vec<(Vec<f64>, Vec<f64>, f64)>,
-) {
+    smoothed_contours: Vec<Contour>,
+) -> Result<(), Error> {
     print!("Culling formlines and rendering contours");
     let _ = stdout().flush();
     let start = Instant::now();
 
     let scalefactor = 1.0;
-    let formlineaddition: f64 = 17.;
-    let minimumgap: u32 = 30;
-    let dashlength: f64 = 60.;
-    let gaplength: f64 = 12.;
+    let formlineaddition: f64 = config.form_lines.additional_tail_length;
+    let minimumgap: u32 = config.form_lines.min_gap_length;
+    let dashlength: f64 = config.form_lines.dash_length;
+    let gaplength: f64 = config.form_lines.gap_length;
     let mut img = RgbaImage::from_pixel(image_width, image_height, TRANSPARENT);
-    let formlinesteepness: f64 = 0.37;
+    let formlinesteepness: f64 = config.form_lines.steepness;
     let buffer_in_pixels = BUFFER as f32 * (config.dpi_resolution / INCH) as f32;
-    let indexcontours: f64 = 25.;
-    let contour_interval: f64 = 5.0;
+    let indexcontours = config.contours.index_multiple as i64;
+    let contour_interval: f64 = config.contours.interval;
     let halfinterval = contour_interval / 2.0 * scalefactor;
 
     let size: f64 = 2.0;
@@ -174,18 +176,16 @@ pub fn pullautin_cull_formlines_render_contours(
     }
 
     let mut id: i32 = 0;
-    let contours_polylines_path = tile.dir_path.join("contours-raw").join("contours-raw.shp");
-
-    let contours_polylines_reader: shapefile::Reader<BufReader<File>, BufReader<File>> =
-        Reader::from_path(&contours_polylines_path).unwrap();
-
-    let table_info = contours_polylines_reader.into_table_info();
-
     let formlines_dir = tile.dir_path.join("formlines");
-    create_dir_all(&formlines_dir).expect("Could not create formlines dir");
+    create_dir_all(&formlines_dir).map_err(file_error(&formlines_dir))?;
+    let formlines_path = formlines_dir.join("formlines.shp");
 
-    let mut writer =
-        Writer::from_path_with_info(formlines_dir.join("formlines.shp"), table_info).unwrap();
+    let table_builder = TableWriterBuilder::new()
+        .add_integer_field("ID".try_into().unwrap())
+        .add_numeric_field("elev".try_into().unwrap(), 12, 3);
+
+    let mut writer = Writer::from_path(&formlines_path, table_builder)
+        .map_err(shapefile_error(&formlines_path))?;
 
     for (x_array, y_array, elevation) in smoothed_contours {
         let mut x = Vec::<f64>::new();
@@ -198,11 +198,12 @@ pub fn pullautin_cull_formlines_render_contours(
 
         let mut curvew = 2.0;
 
-        if elevation.round() as isize % indexcontours as isize == 0 {
+        // Raw contours are generated every half interval, odd levels are form lines
+        let level = (elevation / halfinterval).round() as i64;
+
+        if level % (2 * indexcontours) == 0 {
             curvew = 3.5;
-        } else if elevation.round() as isize % contour_interval as isize != 0
-            && (elevation * 2.).round() as isize % (halfinterval * 2.).round() as isize == 0
-        {
+        } else if level % 2 != 0 {
             curvew = 1.5;
         }
 
@@ -462,11 +463,14 @@ pub fn pullautin_cull_formlines_render_contours(
     }
 
     // TODO: img.save takes 8 seconds, maybe mutualize with other images saving
-    img.save(tile.dir_path.join("contours.png"))
-        .expect("could not save output png");
+    let contours_path = tile.dir_path.join("contours.png");
+    img.save(&contours_path)
+        .map_err(image_error(&contours_path))?;
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
+
+    Ok(())
 }
 
 fn write_formline_shape_to_shapefile(
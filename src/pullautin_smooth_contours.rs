@@ -1,22 +1,45 @@
 use core::f64;
-use shapefile::dbase::{FieldValue, Record};
+use shapefile::dbase::{FieldIOError, FieldWriter, TableWriterBuilder, WritableRecord};
 use shapefile::record::polyline::GenericPolyline;
-use shapefile::{Point, Polyline, Reader};
-use std::fs::{create_dir_all, File};
-use std::io::{stdout, BufReader, Write};
+use shapefile::Point;
+use std::fs::create_dir_all;
+use std::io::{stdout, Write};
 use std::time::Instant;
-use tiff::decoder::{Decoder, DecodingResult};
 
+use crate::config::Config;
 use crate::constants::BUFFER;
+use crate::error::{file_error, shapefile_error, Error};
+use crate::marching_squares::trace_contours;
+use crate::raster::Raster;
 use crate::tile::Tile;
 
-pub fn pullautin_smooth_contours(tile: &Tile) -> (Vec<Vec<f64>>, Vec<(Vec<f64>, Vec<f64>, f64)>) {
+/// The x coordinates, y coordinates and elevation of a contour
+pub type Contour = (Vec<f64>, Vec<f64>, f64);
+
+struct ContourRecord {
+    elev: f64,
+}
+
+impl WritableRecord for ContourRecord {
+    fn write_using<'a, W>(&self, field_writer: &mut FieldWriter<'a, W>) -> Result<(), FieldIOError>
+    where
+        W: Write,
+    {
+        field_writer.write_next_field_value(&self.elev)?;
+        Ok(())
+    }
+}
+
+pub fn pullautin_smooth_contours(
+    tile: &Tile,
+    config: &Config,
+) -> Result<(Vec<Vec<f64>>, Vec<Contour>), Error> {
     print!("Smoothing contours");
     let _ = stdout().flush();
     let start = Instant::now();
 
-    let smoothing: f64 = 0.7;
-    let curviness: f64 = 1.1;
+    let smoothing: f64 = config.contours.smoothing;
+    let curviness: f64 = config.contours.curviness;
 
     let size: f64 = 2.0;
     let xstart: f64 = (tile.min_x - BUFFER as i64) as f64;
@@ -24,9 +47,9 @@ pub fn pullautin_smooth_contours(tile: &Tile) -> (Vec<Vec<f64>>, Vec<(Vec<f64>,
     let xmax: u64 = ((tile.max_x + BUFFER as i64 - xstart as i64) as f64 / 2.).ceil() as u64;
     let ymax: u64 = ((tile.max_y + BUFFER as i64 - ystart as i64) as f64 / 2.).ceil() as u64;
 
-    let avg_alt = get_elevation_matrix_from_dem(tile);
+    let avg_alt = get_elevation_matrix_from_dem(tile)?;
     let mut steepness = vec![vec![f64::NAN; (ymax + 2) as usize]; (xmax + 2) as usize];
-    let mut smoothed_contours: Vec<(Vec<f64>, Vec<f64>, f64)> = vec![];
+    let mut smoothed_contours: Vec<Contour> = vec![];
 
     // Computing a basic steepness matrix
     for i in 1..xmax {
@@ -50,52 +73,31 @@ pub fn pullautin_smooth_contours(tile: &Tile) -> (Vec<Vec<f64>>, Vec<(Vec<f64>,
         }
     }
 
-    let contours_polylines_path = tile.dir_path.join("contours-raw").join("contours-raw.shp");
-
-    let mut contours_polylines_reader: shapefile::Reader<BufReader<File>, BufReader<File>> =
-        Reader::from_path(&contours_polylines_path).unwrap();
-
-    let contours_polylines_reader_for_table_info: shapefile::Reader<
-        BufReader<File>,
-        BufReader<File>,
-    > = Reader::from_path(&contours_polylines_path).unwrap();
-
-    let table_info = contours_polylines_reader_for_table_info.into_table_info();
+    // Tracing contours every half interval so the renderer can turn them into form lines
+    let raw_contours = trace_contours(
+        &avg_alt,
+        xstart,
+        ystart,
+        size,
+        config.contours.interval / 2.0,
+    );
 
     let contours_dir = tile.dir_path.join("contours");
-    create_dir_all(&contours_dir).expect("Could not create contours dir");
-
-    let mut writer =
-        shapefile::Writer::from_path_with_info(contours_dir.join("contours.shp"), table_info)
-            .unwrap();
-
-    for shape_record in contours_polylines_reader.iter_shapes_and_records_as::<Polyline, Record>() {
-        let (line, record) = shape_record.unwrap();
-        let mut x_array = Vec::<f64>::new();
-        let mut y_array = Vec::<f64>::new();
-
-        // Assuming line.parts().len() == 1
-        for part in line.parts() {
-            for point in part {
-                x_array.push(point.x);
-                y_array.push(point.y + 2.); // There was a 2 metters offset between pullautin's raw contours and the one generated by GDAL. Keeping this offset so it doesn't disturb the smoothin process
-            }
-        }
+    create_dir_all(&contours_dir).map_err(file_error(&contours_dir))?;
+    let contours_path = contours_dir.join("contours.shp");
+    let table_builder =
+        TableWriterBuilder::new().add_numeric_field("elev".try_into().unwrap(), 12, 3);
 
-        let elevation = match record.get("elev") {
-            Some(FieldValue::Numeric(Some(x))) => x,
-            Some(_) => &f64::NAN,
-            None => panic!("Field 'elev' is not within polygon-dataset"),
-        };
+    let mut writer = shapefile::Writer::from_path(&contours_path, table_builder)
+        .map_err(shapefile_error(&contours_path))?;
 
+    for (mut x_array, mut y_array, height) in raw_contours {
         let mut el_x_len = x_array.len();
 
         if el_x_len < 15 {
             continue;
         }
 
-        let height = *elevation;
-
         if el_x_len > 101 {
             let mut newx: Vec<f64> = vec![];
             let mut newy: Vec<f64> = vec![];
@@ -263,6 +265,7 @@ pub fn pullautin_smooth_contours(tile: &Tile) -> (Vec<Vec<f64>>, Vec<(Vec<f64>,
         }
 
         let smoothed_polyline = GenericPolyline::new(points);
+        let record = ContourRecord { elev: height };
         let _ = writer.write_shape_and_record(&smoothed_polyline, &record);
         smoothed_contours.push((x_array, y_array, height));
     }
@@ -270,33 +273,61 @@ pub fn pullautin_smooth_contours(tile: &Tile) -> (Vec<Vec<f64>>, Vec<(Vec<f64>,
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
 
-    return (avg_alt, smoothed_contours);
+    Ok((avg_alt, smoothed_contours))
 }
 
-fn get_elevation_matrix_from_dem(tile: &Tile) -> Vec<Vec<f64>> {
+fn get_elevation_matrix_from_dem(tile: &Tile) -> Result<Vec<Vec<f64>>, Error> {
     let dem_path = tile.dir_path.join("dem-low-resolution-with-buffer.tif");
-    let dem_tif_file = File::open(dem_path).expect("Cannot find dem tif image!");
-
-    let mut dem_img_decoder = Decoder::new(dem_tif_file).expect("Cannot create decoder");
-    dem_img_decoder = dem_img_decoder.with_limits(tiff::decoder::Limits::unlimited());
+    let dem = Raster::read_geotiff(&dem_path)?;
 
-    let (dem_width, dem_height) = dem_img_decoder.dimensions().unwrap();
+    Ok(get_elevation_matrix(&dem))
+}
 
-    let width: usize = dem_width as usize;
-    let height: usize = dem_height as usize;
-    let mut avg_alt = vec![vec![f64::NAN; height + 2]; width + 2];
+/// The elevations of a DEM indexed by `[x][y]` with y going north, surrounded by a NaN border.
+/// Nodata cells are NaN too, so no contour is traced around them.
+fn get_elevation_matrix(dem: &Raster) -> Vec<Vec<f64>> {
+    let mut avg_alt = vec![vec![f64::NAN; dem.height + 2]; dem.width + 2];
 
-    let DecodingResult::F64(image_data) = dem_img_decoder.read_image().unwrap() else {
-        panic!("Cannot read band data")
-    };
+    for (column, column_elevations) in avg_alt.iter_mut().take(dem.width).enumerate() {
+        for row in 0..dem.height {
+            let elevation = dem.get(column, row);
 
-    // Building avg_alt matrix and defining hmin and hmax
-    for index in 0..image_data.len() {
-        let x = index % width;
-        let y = height - index / width;
-        let elevation = image_data[index] as f64;
-        avg_alt[x][y] = elevation;
+            if !dem.is_nodata(elevation) {
+                column_elevations[dem.height - row] = elevation;
+            }
+        }
     }
 
-    return avg_alt;
+    avg_alt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::NO_DATA_VALUE;
+
+    #[test]
+    fn skips_nodata_holes() {
+        let mut dem = Raster::new(10, 10, 0., 20., 2., Some(NO_DATA_VALUE), 0.);
+
+        for row in 0..10 {
+            for column in 0..10 {
+                let elevation = if (4..6).contains(&row) && (4..6).contains(&column) {
+                    NO_DATA_VALUE
+                } else {
+                    100. + column as f64
+                };
+
+                dem.set(column, row, elevation);
+            }
+        }
+
+        let avg_alt = get_elevation_matrix(&dem);
+        let contours = trace_contours(&avg_alt, 0., 0., 2., 1.);
+
+        assert!(!contours.is_empty());
+        assert!(contours
+            .iter()
+            .all(|(_, _, elevation)| (100. ..=109.).contains(elevation)));
+    }
 }
@@ -0,0 +1,269 @@
+use crate::error::{file_error, tiff_error, Error};
+use std::{fs::File, path::Path};
+use tiff::{
+    decoder::{Decoder, DecodingResult, Limits},
+    encoder::{colortype::Gray64Float, TiffEncoder},
+    tags::Tag,
+};
+
+pub const NO_DATA_VALUE: f64 = -9999.;
+
+/// A north up raster with square cells, stored row by row from the top left corner.
+#[derive(Clone)]
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    pub min_x: f64,
+    pub max_y: f64,
+    pub resolution: f64,
+    pub nodata: Option<f64>,
+    pub pixels: Vec<f64>,
+}
+
+impl Raster {
+    pub fn new(
+        width: usize,
+        height: usize,
+        min_x: f64,
+        max_y: f64,
+        resolution: f64,
+        nodata: Option<f64>,
+        fill_value: f64,
+    ) -> Raster {
+        Raster {
+            width,
+            height,
+            min_x,
+            max_y,
+            resolution,
+            nodata,
+            pixels: vec![fill_value; width * height],
+        }
+    }
+
+    /// The column and row of the cell containing the given coordinates, if inside the raster.
+    /// Coordinates on the right and bottom edges belong to the last column and row, like in
+    /// PDAL's writers.gdal.
+    pub fn get_cell_index(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        let mut column = ((x - self.min_x) / self.resolution).floor();
+        let mut row = ((self.max_y - y) / self.resolution).floor();
+
+        if column == self.width as f64 && x <= self.min_x + self.width as f64 * self.resolution {
+            column -= 1.;
+        }
+
+        if row == self.height as f64 && y >= self.max_y - self.height as f64 * self.resolution {
+            row -= 1.;
+        }
+
+        if column < 0. || row < 0. || column >= self.width as f64 || row >= self.height as f64 {
+            return None;
+        }
+
+        Some((column as usize, row as usize))
+    }
+
+    pub fn get(&self, column: usize, row: usize) -> f64 {
+        self.pixels[row * self.width + column]
+    }
+
+    pub fn set(&mut self, column: usize, row: usize, value: f64) {
+        self.pixels[row * self.width + column] = value;
+    }
+
+    /// The value of the cell containing the given coordinates, None outside the raster or on nodata.
+    pub fn get_value_at(&self, x: f64, y: f64) -> Option<f64> {
+        let (column, row) = self.get_cell_index(x, y)?;
+        let value = self.get(column, row);
+
+        if self.is_nodata(value) {
+            return None;
+        }
+
+        Some(value)
+    }
+
+    pub fn is_nodata(&self, value: f64) -> bool {
+        match self.nodata {
+            // NaN is never equal to itself
+            Some(nodata) if nodata.is_nan() => value.is_nan(),
+            Some(nodata) => value == nodata,
+            None => false,
+        }
+    }
+
+    /// Reads a single band float GeoTIFF, north up with square cells like the ones GDAL writes.
+    pub fn read_geotiff(path: &Path) -> Result<Raster, Error> {
+        let file = File::open(path).map_err(file_error(path))?;
+        let mut decoder = Decoder::new(file)
+            .map_err(tiff_error(path))?
+            .with_limits(Limits::unlimited());
+
+        let (width, height) = decoder.dimensions().map_err(tiff_error(path))?;
+        let pixel_scale = decoder
+            .get_tag_f64_vec(Tag::ModelPixelScaleTag)
+            .map_err(tiff_error(path))?;
+        let tiepoint = decoder
+            .get_tag_f64_vec(Tag::ModelTiepointTag)
+            .map_err(tiff_error(path))?;
+
+        if pixel_scale.len() < 2 || tiepoint.len() < 6 {
+            return Err(Error::UnexpectedRasterFormat {
+                path: path.to_path_buf(),
+                expected: "georeferenced",
+            });
+        }
+
+        let nodata = decoder
+            .find_tag(Tag::GdalNodata)
+            .map_err(tiff_error(path))?
+            .and_then(|value| value.into_string().ok())
+            .and_then(|value| value.trim_matches(char::from(0)).trim().parse().ok());
+
+        let pixels = match decoder.read_image().map_err(tiff_error(path))? {
+            DecodingResult::F64(pixels) => pixels,
+            DecodingResult::F32(pixels) => pixels.into_iter().map(f64::from).collect(),
+            _ => {
+                return Err(Error::UnexpectedRasterFormat {
+                    path: path.to_path_buf(),
+                    expected: "F32 or F64",
+                })
+            }
+        };
+
+        if pixels.is_empty() {
+            return Err(Error::EmptyRaster(path.to_path_buf()));
+        }
+
+        // The tie point maps a raster position (column, row) to model coordinates (x, y)
+        Ok(Raster {
+            width: width as usize,
+            height: height as usize,
+            min_x: tiepoint[3] - tiepoint[0] * pixel_scale[0],
+            max_y: tiepoint[4] + tiepoint[1] * pixel_scale[1],
+            resolution: pixel_scale[0],
+            nodata,
+            pixels,
+        })
+    }
+
+    /// Writes the raster as a single band float64 GeoTIFF in the given projected CRS, readable by GDAL.
+    pub fn write_geotiff(&self, path: &Path, epsg_code: u16) -> Result<(), Error> {
+        let file = File::create(path).map_err(file_error(path))?;
+        let mut encoder = TiffEncoder::new(file).map_err(tiff_error(path))?;
+        let mut image = encoder
+            .new_image::<Gray64Float>(self.width as u32, self.height as u32)
+            .map_err(tiff_error(path))?;
+
+        let directory = image.encoder();
+
+        directory
+            .write_tag(
+                Tag::ModelPixelScaleTag,
+                &[self.resolution, self.resolution, 0.][..],
+            )
+            .map_err(tiff_error(path))?;
+
+        directory
+            .write_tag(
+                Tag::ModelTiepointTag,
+                &[0., 0., 0., self.min_x, self.max_y, 0.][..],
+            )
+            .map_err(tiff_error(path))?;
+
+        let geo_keys: Vec<u16> = [
+            // Key directory version, revision, minor revision and number of keys
+            [1, 1, 0, 4],
+            // Projected model type
+            [1024, 0, 1, 1],
+            // Pixels represent areas
+            [1025, 0, 1, 1],
+            // Projected CRS
+            [3072, 0, 1, epsg_code],
+            // Linear unit in meters
+            [3076, 0, 1, 9001],
+        ]
+        .concat();
+
+        directory
+            .write_tag(Tag::GeoKeyDirectoryTag, &geo_keys[..])
+            .map_err(tiff_error(path))?;
+
+        if let Some(nodata) = self.nodata {
+            directory
+                .write_tag(Tag::GdalNodata, nodata.to_string().as_str())
+                .map_err(tiff_error(path))?;
+        }
+
+        image.write_data(&self.pixels).map_err(tiff_error(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_raster() -> Raster {
+        Raster::new(4, 3, 1000., 2000., 2., Some(NO_DATA_VALUE), NO_DATA_VALUE)
+    }
+
+    #[test]
+    fn finds_the_cell_of_inner_points() {
+        let raster = create_raster();
+
+        assert_eq!(raster.get_cell_index(1000., 2000.), Some((0, 0)));
+        assert_eq!(raster.get_cell_index(1003.9, 1996.1), Some((1, 1)));
+        assert_eq!(raster.get_cell_index(1004., 1996.), Some((2, 2)));
+    }
+
+    #[test]
+    fn puts_the_points_of_the_right_and_bottom_edges_in_the_last_cells() {
+        let raster = create_raster();
+
+        assert_eq!(raster.get_cell_index(1008., 1999.), Some((3, 0)));
+        assert_eq!(raster.get_cell_index(1001., 1994.), Some((0, 2)));
+        assert_eq!(raster.get_cell_index(1008., 1994.), Some((3, 2)));
+    }
+
+    #[test]
+    fn leaves_out_the_points_outside_of_the_raster() {
+        let raster = create_raster();
+
+        assert_eq!(raster.get_cell_index(999.9, 1999.), None);
+        assert_eq!(raster.get_cell_index(1008.1, 1999.), None);
+        assert_eq!(raster.get_cell_index(1001., 2000.1), None);
+        assert_eq!(raster.get_cell_index(1001., 1993.9), None);
+    }
+
+    #[test]
+    fn matches_nan_nodata_values() {
+        let mut raster = Raster::new(2, 1, 0., 1., 1., Some(f64::NAN), 5.);
+        raster.set(1, 0, f64::NAN);
+
+        assert_eq!(raster.get_value_at(0.5, 0.5), Some(5.));
+        assert_eq!(raster.get_value_at(1.5, 0.5), None);
+        assert!(!create_raster().is_nodata(f64::NAN));
+    }
+
+    #[test]
+    fn reads_back_the_written_geotiff() {
+        let mut raster = create_raster();
+        raster.set(1, 2, 12.5);
+        let path = std::env::temp_dir().join(format!("cassini-raster-{}.tif", std::process::id()));
+
+        raster.write_geotiff(&path, 2154).unwrap();
+        let read_raster = Raster::read_geotiff(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            (read_raster.width, read_raster.height),
+            (raster.width, raster.height)
+        );
+        assert_eq!(
+            (read_raster.min_x, read_raster.max_y, read_raster.resolution),
+            (1000., 2000., 2.)
+        );
+        assert_eq!(read_raster.nodata, Some(NO_DATA_VALUE));
+        assert_eq!(read_raster.pixels, raster.pixels);
+    }
+}
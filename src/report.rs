@@ -0,0 +1,102 @@
+use crate::{
+    error::{file_error, Error},
+    manifest::read_point_density,
+    tile::Tile,
+};
+use serde::Serialize;
+use std::{fs::File, io::Write, path::Path, path::PathBuf};
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct BatchReport {
+    pub succeeded: Vec<TileSuccess>,
+    pub failed: Vec<TileFailure>,
+    pub skipped: Vec<SkippedFile>,
+    /// Why merging the tile maps failed, the tiles themselves being done
+    pub merge_error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TileSuccess {
+    pub tile: PathBuf,
+    pub laz_path: PathBuf,
+    /// Mean number of LiDAR points per square meter
+    pub point_density: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TileFailure {
+    pub tile: PathBuf,
+    pub laz_path: PathBuf,
+    pub stage: Option<String>,
+    pub error: String,
+}
+
+/// An input file that was not turned into a tile.
+#[derive(Serialize, Debug, Clone)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl BatchReport {
+    pub fn add_success(&mut self, tile: &Tile) {
+        self.succeeded.push(TileSuccess {
+            tile: tile.dir_path.clone(),
+            laz_path: tile.laz_path.clone(),
+            point_density: read_point_density(&tile.dir_path),
+        });
+    }
+
+    pub fn add_failure(&mut self, tile: &Tile, error: &Error) {
+        self.failed.push(TileFailure {
+            tile: tile.dir_path.clone(),
+            laz_path: tile.laz_path.clone(),
+            stage: error.stage().map(|stage| stage.to_string()),
+            error: error.full_message(),
+        });
+    }
+
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty() || self.merge_error.is_some()
+    }
+
+    pub fn print(&self) {
+        println!();
+        println!(
+            "Batch report: {} succeeded, {} failed, {} skipped",
+            self.succeeded.len(),
+            self.failed.len(),
+            self.skipped.len()
+        );
+
+        for success in &self.succeeded {
+            match success.point_density {
+                Some(point_density) => println!(
+                    "  [ok]      {:?} ({:.1} points/m²)",
+                    success.tile, point_density
+                ),
+                None => println!("  [ok]      {:?}", success.tile),
+            }
+        }
+
+        for failure in &self.failed {
+            println!("  [failed]  {:?}: {}", failure.tile, failure.error);
+        }
+
+        for skipped in &self.skipped {
+            println!("  [skipped] {:?}: {}", skipped.path, skipped.reason);
+        }
+
+        if let Some(merge_error) = &self.merge_error {
+            println!("  [failed]  merge: {}", merge_error);
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), Error> {
+        let json_string = serde_json::to_string_pretty(self).unwrap();
+        let mut file = File::create(path).map_err(file_error(path))?;
+        file.write_all(json_string.as_bytes())
+            .map_err(file_error(path))?;
+        Ok(())
+    }
+}
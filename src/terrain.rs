@@ -0,0 +1,291 @@
+use crate::raster::{Raster, NO_DATA_VALUE};
+use std::borrow::Cow;
+
+/// Slope, aspect and curvatures of a DEM, computed on the 3 by 3 window around every cell. Every
+/// derivative is only computed when asked for, as a raster whose cells on the edges of the DEM or
+/// next to a hole are nodata.
+pub struct TerrainDerivatives<'a> {
+    dem: Cow<'a, Raster>,
+}
+
+impl<'a> TerrainDerivatives<'a> {
+    /// Prepares the derivatives of a DEM, first averaged over the cells closer than
+    /// `smoothing_radius` meters when not 0, so the noise of the ground points does not show up
+    /// in the curvatures.
+    pub fn new(dem: &'a Raster, smoothing_radius: f64) -> TerrainDerivatives<'a> {
+        let smoothing_cells = (smoothing_radius / dem.resolution).round() as usize;
+
+        let dem = if smoothing_cells > 0 {
+            Cow::Owned(smooth(dem, smoothing_cells))
+        } else {
+            Cow::Borrowed(dem)
+        };
+
+        TerrainDerivatives { dem }
+    }
+
+    /// In degrees from the horizontal.
+    pub fn slope(&self) -> Raster {
+        self.map_windows(|window, resolution| {
+            let (horn_x, horn_y) = get_horn_gradient(window, resolution);
+            Some(horn_x.hypot(horn_y).atan().to_degrees())
+        })
+    }
+
+    /// Computes a raster from the 3 by 3 window around every cell of the DEM, read row by row
+    /// from the north west corner. Windows with nodata and `None` values give nodata cells.
+    fn map_windows(&self, derivative: impl Fn([f64; 9], f64) -> Option<f64>) -> Raster {
+        let dem = &self.dem;
+        let mut raster = Raster::new(
+            dem.width,
+            dem.height,
+            dem.min_x,
+            dem.max_y,
+            dem.resolution,
+            Some(NO_DATA_VALUE),
+            NO_DATA_VALUE,
+        );
+
+        for row in 1..dem.height.saturating_sub(1) {
+            for column in 1..dem.width.saturating_sub(1) {
+                let mut window = [0.; 9];
+                let mut has_nodata = false;
+
+                for (index, value) in window.iter_mut().enumerate() {
+                    *value = dem.get(column + index % 3 - 1, row + index / 3 - 1);
+                    has_nodata |= dem.is_nodata(*value);
+                }
+
+                if has_nodata {
+                    continue;
+                }
+
+                if let Some(value) = derivative(window, dem.resolution) {
+                    raster.set(column, row, value);
+                }
+            }
+        }
+
+        raster
+    }
+}
+
+// Not used by the cliff detection, the other landform detectors will share them
+#[allow(dead_code)]
+impl TerrainDerivatives<'_> {
+    /// The downslope direction in degrees clockwise from the north, nodata on flat cells.
+    pub fn aspect(&self) -> Raster {
+        self.map_windows(|window, resolution| {
+            let (horn_x, horn_y) = get_horn_gradient(window, resolution);
+
+            if horn_x == 0. && horn_y == 0. {
+                return None;
+            }
+
+            Some((-horn_x).atan2(-horn_y).to_degrees().rem_euclid(360.))
+        })
+    }
+
+    /// The curvature along the slope, in 1/m, positive where the slope gets gentler downhill.
+    pub fn profile_curvature(&self) -> Raster {
+        self.map_windows(|window, resolution| {
+            let [z_x, z_y, z_xx, z_yy, z_xy] = get_second_order_derivatives(window, resolution);
+            let gradient = z_x * z_x + z_y * z_y;
+
+            if gradient == 0. {
+                return Some(0.);
+            }
+
+            Some(
+                (z_xx * z_x * z_x + 2. * z_xy * z_x * z_y + z_yy * z_y * z_y)
+                    / (gradient * (1. + gradient).powf(1.5)),
+            )
+        })
+    }
+
+    /// The curvature across the slope, in 1/m, positive where water converges, like in valleys.
+    pub fn plan_curvature(&self) -> Raster {
+        self.map_windows(|window, resolution| {
+            let [z_x, z_y, z_xx, z_yy, z_xy] = get_second_order_derivatives(window, resolution);
+            let gradient = z_x * z_x + z_y * z_y;
+
+            if gradient == 0. {
+                return Some(0.);
+            }
+
+            Some((z_xx * z_y * z_y - 2. * z_xy * z_x * z_y + z_yy * z_x * z_x) / gradient.powf(1.5))
+        })
+    }
+}
+
+/// Horn's method, like gdaldem, with y going north.
+fn get_horn_gradient(window: [f64; 9], resolution: f64) -> (f64, f64) {
+    // a b c
+    // d e f
+    // g h i
+    let [a, b, c, d, _, f, g, h, i] = window;
+
+    (
+        ((c + 2. * f + i) - (a + 2. * d + g)) / (8. * resolution),
+        ((a + 2. * b + c) - (g + 2. * h + i)) / (8. * resolution),
+    )
+}
+
+/// Zevenbergen and Thorne's derivatives: z_x, z_y, z_xx, z_yy and z_xy, with y going north.
+fn get_second_order_derivatives(window: [f64; 9], resolution: f64) -> [f64; 5] {
+    let [a, b, c, d, e, f, g, h, i] = window;
+
+    [
+        (f - d) / (2. * resolution),
+        (b - h) / (2. * resolution),
+        (d - 2. * e + f) / (resolution * resolution),
+        (b - 2. * e + h) / (resolution * resolution),
+        (c + g - a - i) / (4. * resolution * resolution),
+    ]
+}
+
+/// The mean of the valid cells of the square window around every valid cell.
+fn smooth(dem: &Raster, radius: usize) -> Raster {
+    let mut smoothed_dem = dem.clone();
+
+    for row in 0..dem.height {
+        for column in 0..dem.width {
+            if dem.is_nodata(dem.get(column, row)) {
+                continue;
+            }
+
+            let mut sum = 0.;
+            let mut count = 0;
+
+            for other_row in row.saturating_sub(radius)..(row + radius + 1).min(dem.height) {
+                for other_column in
+                    column.saturating_sub(radius)..(column + radius + 1).min(dem.width)
+                {
+                    let value = dem.get(other_column, other_row);
+
+                    if !dem.is_nodata(value) {
+                        sum += value;
+                        count += 1;
+                    }
+                }
+            }
+
+            smoothed_dem.set(column, row, sum / count as f64);
+        }
+    }
+
+    smoothed_dem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTION: f64 = 2.;
+
+    /// A DEM of the given function of the coordinates in meters from its north west cell, with y
+    /// going north.
+    fn create_dem(size: usize, elevation: impl Fn(f64, f64) -> f64) -> Raster {
+        let mut dem = Raster::new(
+            size,
+            size,
+            0.,
+            0.,
+            RESOLUTION,
+            Some(NO_DATA_VALUE),
+            NO_DATA_VALUE,
+        );
+
+        for row in 0..size {
+            for column in 0..size {
+                let x = column as f64 * RESOLUTION;
+                let y = -(row as f64) * RESOLUTION;
+                dem.set(column, row, elevation(x, y));
+            }
+        }
+
+        dem
+    }
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 1e-9,
+            "{} is not {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn computes_the_horn_slope_of_a_plane() {
+        let dem = create_dem(5, |x, y| 100. + 0.5 * x + 0.25 * y);
+        let terrain_derivatives = TerrainDerivatives::new(&dem, 0.);
+        let slope = terrain_derivatives.slope();
+        let aspect = terrain_derivatives.aspect();
+
+        let expected_slope = 0.5_f64.hypot(0.25).atan().to_degrees();
+        // Going down towards the west and a bit towards the south
+        let expected_aspect = 180. + 0.5_f64.atan2(0.25).to_degrees();
+
+        for row in 1..4 {
+            for column in 1..4 {
+                assert_close(slope.get(column, row), expected_slope);
+                assert_close(aspect.get(column, row), expected_aspect);
+            }
+        }
+
+        // The edges have no full window
+        assert!(slope.is_nodata(slope.get(0, 2)));
+        assert!(slope.is_nodata(slope.get(2, 4)));
+    }
+
+    #[test]
+    fn leaves_the_aspect_of_flat_cells_and_cells_next_to_holes_nodata() {
+        let mut dem = create_dem(5, |_, _| 100.);
+        dem.set(3, 3, NO_DATA_VALUE);
+        let terrain_derivatives = TerrainDerivatives::new(&dem, 0.);
+        let slope = terrain_derivatives.slope();
+        let aspect = terrain_derivatives.aspect();
+
+        assert_close(slope.get(1, 1), 0.);
+        assert!(aspect.is_nodata(aspect.get(1, 1)));
+        assert!(slope.is_nodata(slope.get(2, 2)));
+    }
+
+    #[test]
+    fn computes_the_zevenbergen_thorne_curvatures_of_a_paraboloid() {
+        // A bowl centered on the cell (5, 5)
+        let (center_x, center_y) = (5. * RESOLUTION, -5. * RESOLUTION);
+        let dem = create_dem(11, |x, y| (x - center_x).powi(2) + (y - center_y).powi(2));
+        let terrain_derivatives = TerrainDerivatives::new(&dem, 0.);
+        let profile_curvature = terrain_derivatives.profile_curvature();
+        let plan_curvature = terrain_derivatives.plan_curvature();
+
+        for (column, row) in [(7, 5), (5, 2), (8, 9), (3, 4)] {
+            let x = (column as f64 - 5.) * RESOLUTION;
+            let y = (5. - row as f64) * RESOLUTION;
+            // The squared gradient of x² + y²
+            let gradient = 4. * (x * x + y * y);
+
+            // The slope gets gentler down to the center, and the water converges there
+            assert_close(
+                profile_curvature.get(column, row),
+                2. / (1. + gradient).powf(1.5),
+            );
+            assert_close(plan_curvature.get(column, row), 2. / gradient.sqrt());
+        }
+
+        assert_close(profile_curvature.get(5, 5), 0.);
+        assert_close(plan_curvature.get(5, 5), 0.);
+    }
+
+    #[test]
+    fn smooths_the_dem_before_computing_derivatives() {
+        let dem = create_dem(7, |x, _| if x > 6. { 10. } else { 0. });
+        let sharp_slope = TerrainDerivatives::new(&dem, 0.).slope();
+        let smoothed_slope = TerrainDerivatives::new(&dem, RESOLUTION).slope();
+
+        assert!(smoothed_slope.get(3, 3) < sharp_slope.get(3, 3));
+        assert!(smoothed_slope.get(2, 3) > sharp_slope.get(2, 3));
+    }
+}
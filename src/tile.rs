@@ -14,16 +14,9 @@ pub struct Tile {
     pub min_y: i64,
     pub max_x: i64,
     pub max_y: i64,
+    pub epsg_code: u16,
 }
 
-#[derive(Debug, Clone)]
-pub struct NeighborTiles {
-    pub top: Option<Tile>,
-    pub top_right: Option<Tile>,
-    pub right: Option<Tile>,
-    pub bottom_right: Option<Tile>,
-    pub bottom: Option<Tile>,
-    pub bottom_left: Option<Tile>,
-    pub left: Option<Tile>,
-    pub top_left: Option<Tile>,
-}
+/// The tiles whose footprint intersects the buffered extent of a tile, in no particular layout:
+/// surveys cropped at their edges or mixing tile sizes do not form a regular grid.
+pub type NeighborTiles = Vec<Tile>;
@@ -0,0 +1,109 @@
+use crate::tile::Tile;
+use std::collections::HashMap;
+
+/// A spatial index over tile footprints. Tiles are bucketed in a regular grid of cells as large as
+/// the largest tile, so finding the tiles intersecting an area only looks at a few cells.
+pub struct TileIndex {
+    tiles: Vec<Tile>,
+    cell_size: i64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl TileIndex {
+    pub fn new(tiles: Vec<Tile>) -> TileIndex {
+        let cell_size = tiles
+            .iter()
+            .map(|tile| (tile.max_x - tile.min_x).max(tile.max_y - tile.min_y))
+            .max()
+            .unwrap_or(1)
+            .max(1);
+
+        let mut tile_index = TileIndex {
+            tiles: vec![],
+            cell_size,
+            cells: HashMap::new(),
+        };
+
+        for (index, tile) in tiles.iter().enumerate() {
+            for cell in tile_index.get_cells(tile.min_x, tile.min_y, tile.max_x, tile.max_y) {
+                tile_index.cells.entry(cell).or_default().push(index);
+            }
+        }
+
+        tile_index.tiles = tiles;
+        tile_index
+    }
+
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    /// The tiles whose footprint overlaps the given area, sorted by directory so the result does
+    /// not depend on the discovery order. Tiles only touching the area are left out.
+    pub fn get_intersecting_tiles(
+        &self,
+        min_x: i64,
+        min_y: i64,
+        max_x: i64,
+        max_y: i64,
+    ) -> Vec<&Tile> {
+        let mut indexes: Vec<usize> = self
+            .get_cells(min_x, min_y, max_x, max_y)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .filter(|&index| {
+                let tile = &self.tiles[index];
+                tile.min_x < max_x && tile.max_x > min_x && tile.min_y < max_y && tile.max_y > min_y
+            })
+            .collect();
+
+        // A tile spanning several cells is found once per cell
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let mut tiles: Vec<&Tile> = indexes
+            .into_iter()
+            .map(|index| &self.tiles[index])
+            .collect();
+
+        tiles.sort_by(|a, b| a.dir_path.cmp(&b.dir_path));
+        tiles
+    }
+
+    /// The tiles intersecting the extent of a tile grown by the buffer, except the tile itself and
+    /// the tiles with the same bounds.
+    pub fn get_neighbor_tiles(&self, tile: &Tile, buffer: i64) -> Vec<Tile> {
+        self.get_intersecting_tiles(
+            tile.min_x - buffer,
+            tile.min_y - buffer,
+            tile.max_x + buffer,
+            tile.max_y + buffer,
+        )
+        .into_iter()
+        .filter(|other_tile| {
+            (
+                other_tile.min_x,
+                other_tile.min_y,
+                other_tile.max_x,
+                other_tile.max_y,
+            ) != (tile.min_x, tile.min_y, tile.max_x, tile.max_y)
+        })
+        .cloned()
+        .collect()
+    }
+
+    fn get_cells(
+        &self,
+        min_x: i64,
+        min_y: i64,
+        max_x: i64,
+        max_y: i64,
+    ) -> impl Iterator<Item = (i64, i64)> {
+        let cell_size = self.cell_size;
+        let columns = min_x.div_euclid(cell_size)..=max_x.div_euclid(cell_size);
+        let rows = min_y.div_euclid(cell_size)..=max_y.div_euclid(cell_size);
+
+        columns.flat_map(move |column| rows.clone().map(move |row| (column, row)))
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{
+    buffer::mosaic_with_buffer,
+    canvas::Canvas,
+    config::Config,
+    constants::{
+        BUFFER, INCH, PROMINENT_LARGE_TREE_DIAMETER, PROMINENT_LARGE_TREE_OUTLINE_WIDTH,
+        VECTOR_GREEN,
+    },
+    error::{file_error, Error},
+    raster::Raster,
+    tile::{NeighborTiles, Tile},
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    fs::File,
+    io::{stdout, Write},
+    time::Instant,
+};
+
+/// Lower local maxima of the canopy height model are bushes, rocks or building edges.
+const MIN_TREE_HEIGHT: f64 = 2.0;
+const MAX_CROWN_RADIUS: f64 = 15.0;
+const CIRCLE_SEGMENTS: usize = 24;
+
+const DIRECTIONS: [(i64, i64); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// A local maximum of the canopy height model, with its height above ground and the estimated
+/// radius of its crown, in meters.
+#[derive(Serialize)]
+struct TreeTop {
+    x: f64,
+    y: f64,
+    height: f64,
+    crown_radius: f64,
+}
+
+/// Finds the tree tops of the canopy height model of a tile, writes them to `trees.json` and
+/// draws the isolated large ones as prominent large trees.
+pub fn render_trees(
+    tile: &Tile,
+    neighbor_tiles: &NeighborTiles,
+    image_width: u32,
+    image_height: u32,
+    config: &Config,
+) -> Result<(), Error> {
+    print!("Rendering trees");
+    let _ = stdout().flush();
+    let start = Instant::now();
+
+    // Tree tops close to the tile edges need the canopy of the neighbors to be told apart
+    let canopy_height_model = mosaic_with_buffer(tile, neighbor_tiles, BUFFER as i64, "chm")?;
+    let tree_tops = find_tree_tops(&canopy_height_model);
+
+    let is_in_tile = |tree_top: &TreeTop| {
+        tree_top.x >= tile.min_x as f64
+            && tree_top.x < tile.max_x as f64
+            && tree_top.y > tile.min_y as f64
+            && tree_top.y <= tile.max_y as f64
+    };
+
+    let tile_tree_tops: Vec<&TreeTop> = tree_tops.iter().filter(|top| is_in_tile(top)).collect();
+    let trees_path = tile.dir_path.join("trees.json");
+    let json_string = serde_json::to_string_pretty(&tile_tree_tops).unwrap();
+    let mut file = File::create(&trees_path).map_err(file_error(&trees_path))?;
+    file.write_all(json_string.as_bytes())
+        .map_err(file_error(&trees_path))?;
+
+    let isolation_grid = IsolationGrid::new(&tree_tops, config.trees.isolation_distance);
+    let scale_factor = config.dpi_resolution / INCH;
+    let radius = PROMINENT_LARGE_TREE_DIAMETER / 2. * config.dpi_resolution * 10. / INCH;
+    let mut trees_canvas = Canvas::new(image_width as i32, image_height as i32);
+    trees_canvas.set_color(VECTOR_GREEN);
+    trees_canvas
+        .set_line_width(PROMINENT_LARGE_TREE_OUTLINE_WIDTH * config.dpi_resolution * 10. / INCH);
+
+    // 418 prominent large tree
+    for tree_top in tile_tree_tops {
+        if tree_top.height < config.trees.min_height || !isolation_grid.is_isolated(tree_top) {
+            continue;
+        }
+
+        let x_pixel = (tree_top.x - tile.min_x as f64) as f32 * scale_factor;
+        let y_pixel = (tile.max_y as f64 - tree_top.y) as f32 * scale_factor;
+
+        let circle: Vec<(f32, f32)> = (0..=CIRCLE_SEGMENTS)
+            .map(|index| {
+                let angle = 2. * PI * index as f32 / CIRCLE_SEGMENTS as f32;
+                (
+                    x_pixel + radius * angle.cos(),
+                    y_pixel + radius * angle.sin(),
+                )
+            })
+            .collect();
+
+        trees_canvas.draw_polyline(&circle);
+    }
+
+    trees_canvas.save_as(&tile.dir_path.join("trees.png"))?;
+
+    let duration = start.elapsed();
+    println!(" -> Done in {:.1?}", duration);
+
+    Ok(())
+}
+
+/// Local maxima of the canopy height model, in a window growing with the height of the cell
+/// since taller trees have wider crowns. Of two equal neighbor cells, the first one wins.
+fn find_tree_tops(canopy_height_model: &Raster) -> Vec<TreeTop> {
+    let mut tree_tops = vec![];
+
+    for row in 0..canopy_height_model.height {
+        for column in 0..canopy_height_model.width {
+            let height = canopy_height_model.get(column, row);
+
+            if height < MIN_TREE_HEIGHT || canopy_height_model.is_nodata(height) {
+                continue;
+            }
+
+            let window_radius = (1. + 0.05 * height) / canopy_height_model.resolution;
+            let window_cells = window_radius.ceil() as i64;
+            let mut is_local_maximum = true;
+
+            'window: for row_offset in -window_cells..=window_cells {
+                for column_offset in -window_cells..=window_cells {
+                    if (row_offset == 0 && column_offset == 0)
+                        || ((row_offset * row_offset + column_offset * column_offset) as f64)
+                            > window_radius * window_radius
+                    {
+                        continue;
+                    }
+
+                    let Some(other_height) = get_height(
+                        canopy_height_model,
+                        column as i64 + column_offset,
+                        row as i64 + row_offset,
+                    ) else {
+                        continue;
+                    };
+
+                    let is_before = (row_offset, column_offset) < (0, 0);
+
+                    if other_height > height || (other_height == height && is_before) {
+                        is_local_maximum = false;
+                        break 'window;
+                    }
+                }
+            }
+
+            if !is_local_maximum {
+                continue;
+            }
+
+            let resolution = canopy_height_model.resolution;
+
+            tree_tops.push(TreeTop {
+                x: canopy_height_model.min_x + (column as f64 + 0.5) * resolution,
+                y: canopy_height_model.max_y - (row as f64 + 0.5) * resolution,
+                height,
+                crown_radius: get_crown_radius(canopy_height_model, column, row),
+            });
+        }
+    }
+
+    tree_tops
+}
+
+/// The mean distance from the tree top to the crown edge in 8 directions. The crown ends where
+/// the canopy drops below half the tree height, or rises again towards another tree.
+fn get_crown_radius(canopy_height_model: &Raster, column: usize, row: usize) -> f64 {
+    let resolution = canopy_height_model.resolution;
+    let top_height = canopy_height_model.get(column, row);
+    let mut total_radius = 0.;
+
+    for (column_step, row_step) in DIRECTIONS {
+        let step_length = ((column_step * column_step + row_step * row_step) as f64).sqrt();
+        let mut previous_height = top_height;
+        let mut steps = 0;
+
+        loop {
+            let next_distance = (steps + 1) as f64 * step_length * resolution;
+
+            let Some(height) = get_height(
+                canopy_height_model,
+                column as i64 + (steps + 1) * column_step,
+                row as i64 + (steps + 1) * row_step,
+            ) else {
+                break;
+            };
+
+            if next_distance > MAX_CROWN_RADIUS
+                || height < top_height / 2.
+                || height > previous_height
+            {
+                break;
+            }
+
+            previous_height = height;
+            steps += 1;
+        }
+
+        // The crown edge is half a cell past the last cell of the crown
+        total_radius += (steps as f64 + 0.5) * step_length * resolution;
+    }
+
+    total_radius / DIRECTIONS.len() as f64
+}
+
+fn get_height(canopy_height_model: &Raster, column: i64, row: i64) -> Option<f64> {
+    if column < 0
+        || row < 0
+        || column >= canopy_height_model.width as i64
+        || row >= canopy_height_model.height as i64
+    {
+        return None;
+    }
+
+    let height = canopy_height_model.get(column as usize, row as usize);
+
+    if canopy_height_model.is_nodata(height) {
+        return None;
+    }
+
+    Some(height)
+}
+
+/// Tree tops bucketed in square cells as large as the isolation distance, so only the tree tops
+/// of the 9 cells around a tree top can be closer than the isolation distance.
+struct IsolationGrid<'a> {
+    isolation_distance: f64,
+    cell_size: f64,
+    cells: HashMap<(i64, i64), Vec<&'a TreeTop>>,
+}
+
+impl<'a> IsolationGrid<'a> {
+    fn new(tree_tops: &'a [TreeTop], isolation_distance: f64) -> IsolationGrid<'a> {
+        let cell_size = isolation_distance.max(1.);
+        let mut cells: HashMap<(i64, i64), Vec<&TreeTop>> = HashMap::new();
+
+        for tree_top in tree_tops {
+            cells
+                .entry(get_cell(tree_top, cell_size))
+                .or_default()
+                .push(tree_top);
+        }
+
+        IsolationGrid {
+            isolation_distance,
+            cell_size,
+            cells,
+        }
+    }
+
+    fn is_isolated(&self, tree_top: &TreeTop) -> bool {
+        let (column, row) = get_cell(tree_top, self.cell_size);
+
+        (column - 1..=column + 1)
+            .flat_map(|column| (row - 1..=row + 1).map(move |row| (column, row)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(|other_tree_top| !std::ptr::eq(**other_tree_top, tree_top))
+            .all(|other_tree_top| {
+                (other_tree_top.x - tree_top.x).hypot(other_tree_top.y - tree_top.y)
+                    >= self.isolation_distance
+            })
+    }
+}
+
+fn get_cell(tree_top: &TreeTop, cell_size: f64) -> (i64, i64) {
+    (
+        (tree_top.x / cell_size).floor() as i64,
+        (tree_top.y / cell_size).floor() as i64,
+    )
+}
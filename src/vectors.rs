@@ -1,5 +1,6 @@
 use crate::{
-    canvas::Canvas,
+    canvas::{Canvas, StripesDirection},
+    command::run_command,
     config::Config,
     constants::{
         BUILDING_OUTLINE_WIDTH, CROSSABLE_WATERCOURSE_WIDTH, FOOTPATH_DASH_INTERVAL_LENGTH,
@@ -8,6 +9,7 @@ use crate::{
         VECTOR_BUILDING_GRAY, VECTOR_PAVED_AREA_BROWN, WIDE_ROAD_INNER_WIDTH,
         WIDE_ROAD_OUTER_WIDTH,
     },
+    error::{shapefile_error, Error},
     tile::Tile,
 };
 use shapefile::{
@@ -19,39 +21,43 @@ use shapefile::{
 use std::{
     io::{stdout, Write},
     path::{Path, PathBuf},
-    process::{Command, ExitStatus},
+    process::Command,
     time::Instant,
 };
 
-pub fn render_osm_vector_shapes(tile: &Tile, image_width: u32, image_height: u32, config: &Config) {
+pub fn render_osm_vector_shapes(
+    tile: &Tile,
+    image_width: u32,
+    image_height: u32,
+    config: &Config,
+) -> Result<(), Error> {
     print!("Transforming osm file to shapefiles");
     let _ = stdout().flush();
     let start = Instant::now();
 
     let scale_factor = config.dpi_resolution / INCH;
     let shapes_outlput_path = tile.dir_path.join("shapes");
-    let osm_path = Path::new("in").join(format!("{:0>7}_{:0>7}.osm", tile.min_x, tile.max_y));
-
-    let ogr2ogr_output = Command::new("ogr2ogr")
-        .args([
-            "--config",
-            "OSM_USE_CUSTOM_INDEXING",
-            "NO",
-            "-skipfailures",
-            "-f",
-            "ESRI Shapefile",
-            &shapes_outlput_path.to_str().unwrap(),
-            &osm_path.to_str().unwrap(),
-            "-t_srs",
-            "EPSG:2154",
-        ])
-        .arg("--quiet")
-        .output()
-        .expect("failed to execute ogr2ogr command");
-
-    if !ExitStatus::success(&ogr2ogr_output.status) {
-        println!("{}", String::from_utf8(ogr2ogr_output.stderr).unwrap());
-    }
+    let osm_path = config
+        .input_dir
+        .join(format!("{:0>7}_{:0>7}.osm", tile.min_x, tile.max_y));
+
+    run_command(
+        Command::new("ogr2ogr")
+            .args([
+                "--config",
+                "OSM_USE_CUSTOM_INDEXING",
+                "NO",
+                "-skipfailures",
+                "-overwrite",
+                "-f",
+                "ESRI Shapefile",
+            ])
+            .arg(&shapes_outlput_path)
+            .arg(&osm_path)
+            .arg("-t_srs")
+            .arg(format!("EPSG:{}", tile.epsg_code))
+            .arg("--quiet"),
+    )?;
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
@@ -61,8 +67,8 @@ pub fn render_osm_vector_shapes(tile: &Tile, image_width: u32, image_height: u32
     let start = Instant::now();
 
     let multipolygons_path = shapes_outlput_path.join("multipolygons.shp");
-    let multipolygons = read_as::<_, Polygon, Record>(multipolygons_path)
-        .expect("Could not open multipolygons shapefile");
+    let multipolygons = read_as::<_, Polygon, Record>(&multipolygons_path)
+        .map_err(shapefile_error(&multipolygons_path))?;
 
     let mut map_renderer = MapRenderer::new(
         tile.min_x,
@@ -77,7 +83,7 @@ pub fn render_osm_vector_shapes(tile: &Tile, image_width: u32, image_height: u32
         let natural = match record.get("natural") {
             Some(FieldValue::Character(Some(x))) => x,
             Some(_) => "",
-            None => panic!("Field 'natural' is not within polygon-dataset"),
+            None => return Err(missing_field_error(&multipolygons_path, "natural")),
         };
 
         // 308 marsh
@@ -101,7 +107,7 @@ pub fn render_osm_vector_shapes(tile: &Tile, image_width: u32, image_height: u32
         let building = match record.get("building") {
             Some(FieldValue::Character(Some(x))) => x,
             Some(_) => "",
-            None => panic!("Field 'natural' is not within polygon-dataset"),
+            None => return Err(missing_field_error(&multipolygons_path, "building")),
         };
 
         // 521 building
@@ -118,13 +124,14 @@ pub fn render_osm_vector_shapes(tile: &Tile, image_width: u32, image_height: u32
     }
 
     let lines_path = shapes_outlput_path.join("lines.shp");
-    let lines = read_as::<_, Polyline, Record>(lines_path).expect("Could not open lines shapefile");
+    let lines =
+        read_as::<_, Polyline, Record>(&lines_path).map_err(shapefile_error(&lines_path))?;
 
     for (line, record) in lines {
         let highway = match record.get("highway") {
             Some(FieldValue::Character(Some(x))) => x,
             Some(_) => "",
-            None => panic!("Field 'highway' is not within polygon-dataset"),
+            None => return Err(missing_field_error(&lines_path, "highway")),
         };
 
         // 502 wide road
@@ -157,7 +164,7 @@ pub fn render_osm_vector_shapes(tile: &Tile, image_width: u32, image_height: u32
         let waterway = match record.get("waterway") {
             Some(FieldValue::Character(Some(x))) => x,
             Some(_) => "",
-            None => panic!("Field 'waterway' is not within polygon-dataset"),
+            None => return Err(missing_field_error(&lines_path, "waterway")),
         };
 
         // 304 crossable watercourse
@@ -167,10 +174,19 @@ pub fn render_osm_vector_shapes(tile: &Tile, image_width: u32, image_height: u32
         }
     }
 
-    map_renderer.save_as(tile.dir_path.join("vectors.png"));
+    map_renderer.save_as(tile.dir_path.join("vectors.png"))?;
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
+
+    Ok(())
+}
+
+fn missing_field_error(path: &Path, field: &str) -> Error {
+    Error::MissingShapefileField {
+        path: path.to_path_buf(),
+        field: field.to_owned(),
+    }
 }
 
 struct MapRenderer {
@@ -178,7 +194,6 @@ struct MapRenderer {
     striped_img: Canvas,
     min_x: i64,
     min_y: i64,
-    image_width: u32,
     image_height: u32,
     scale_factor: f32,
     dpi_resolution: f32,
@@ -198,7 +213,6 @@ impl MapRenderer {
             striped_img: Canvas::new(image_width as i32, image_height as i32),
             min_x,
             min_y,
-            image_width,
             image_height,
             scale_factor,
             dpi_resolution,
@@ -321,29 +335,14 @@ impl MapRenderer {
     }
 
     #[inline]
-    fn save_as(mut self, path: PathBuf) {
-        let pixel_marsh_interval =
-            (MARSH_LINE_WIDTH + MARSH_LINE_SPACING) * self.dpi_resolution * 10.0 / INCH;
-
-        let number_of_stripes = self.image_height / pixel_marsh_interval as u32;
-        self.striped_img.set_transparent_color();
-
-        for i in 0..number_of_stripes {
-            let min_y = i as f32 * pixel_marsh_interval;
-
-            let max_y = i as f32 * pixel_marsh_interval
-                + MARSH_LINE_SPACING * self.dpi_resolution * 10.0 / INCH;
-
-            self.striped_img.draw_filled_polygon(&vec![
-                (0., min_y),
-                (self.image_width as f32, min_y),
-                (self.image_width as f32, max_y),
-                (0. as f32, max_y),
-                (0. as f32, min_y),
-            ])
-        }
+    fn save_as(mut self, path: PathBuf) -> Result<(), Error> {
+        self.striped_img.erase_stripes(
+            MARSH_LINE_WIDTH * self.dpi_resolution * 10.0 / INCH,
+            MARSH_LINE_SPACING * self.dpi_resolution * 10.0 / INCH,
+            StripesDirection::Horizontal,
+        );
 
         self.img.overlay(&mut self.striped_img, 0., 0.);
-        self.img.save_as(path.to_str().unwrap());
+        self.img.save_as(&path)
     }
 }
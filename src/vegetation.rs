@@ -1,18 +1,21 @@
 use crate::{
-    buffer::create_tif_with_buffer,
+    buffer::mosaic_with_buffer,
+    canvas::{Canvas, StripesDirection},
     config::Config,
-    constants::{BUFFER, GREEN_1, GREEN_2, GREEN_3, INCH, VEGETATION_BLOCK_SIZE, WHITE, YELLOW},
+    constants::{
+        BUFFER, GREEN_1, GREEN_2, GREEN_3, INCH, UNDERGROWTH_LINE_SPACING, UNDERGROWTH_LINE_WIDTH,
+        VEGETATION_BLOCK_SIZE, WHITE, YELLOW,
+    },
+    error::{image_error, Error},
+    raster::Raster,
     tile::{NeighborTiles, Tile},
 };
 use image::{Rgba, RgbaImage};
 use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
 use std::{
-    fs::File,
     io::{stdout, Write},
-    path::PathBuf,
     time::Instant,
 };
-use tiff::decoder::{Decoder, DecodingResult};
 
 pub fn render_vegetation(
     tile: &Tile,
@@ -20,7 +23,7 @@ pub fn render_vegetation(
     image_width: u32,
     image_height: u32,
     config: &Config,
-) {
+) -> Result<(), Error> {
     print!("Rendering vegetation");
     let _ = stdout().flush();
     let start = Instant::now();
@@ -28,23 +31,40 @@ pub fn render_vegetation(
     let vegetation_block_size_pixel = VEGETATION_BLOCK_SIZE as f32 * config.dpi_resolution / INCH;
     let casted_vegetation_block_size_pixel = vegetation_block_size_pixel.ceil() as u32;
 
-    create_tif_with_buffer(tile, neighbor_tiles, BUFFER as i64, "high-vegetation");
-    create_tif_with_buffer(tile, neighbor_tiles, BUFFER as i64, "medium-vegetation");
+    let yellow_band = &config.lidar.yellow_band;
+    let green_band = &config.lidar.green_band;
 
-    let high_vegetation =
-        get_image_data_from_tif(&tile.dir_path.join("high-vegetation-with-buffer.tif"));
-    let medium_vegetation =
-        get_image_data_from_tif(&tile.dir_path.join("medium-vegetation-with-buffer.tif"));
+    let high_vegetation = mosaic_with_buffer(tile, neighbor_tiles, BUFFER as i64, yellow_band)?;
+    let medium_vegetation = mosaic_with_buffer(tile, neighbor_tiles, BUFFER as i64, green_band)?;
+
+    let undergrowth = match &config.undergrowth.band {
+        Some(undergrowth_band) => Some(mosaic_with_buffer(
+            tile,
+            neighbor_tiles,
+            BUFFER as i64,
+            undergrowth_band,
+        )?),
+        None => None,
+    };
 
     let mut vegetation_layer_img = RgbaImage::from_pixel(image_width, image_height, WHITE);
+    // Undergrowth is drawn on its own layer, as stripes are then cut through all of it at once
+    let mut undergrowth_canvas = undergrowth
+        .as_ref()
+        .map(|_| Canvas::new(image_width as i32, image_height as i32));
 
     for x_index in BUFFER..((tile.max_x + BUFFER as i64 - tile.min_x) as usize) {
         for y_index in BUFFER..((tile.max_y + BUFFER as i64 - tile.min_y) as usize) {
             let x_pixel = ((x_index - BUFFER) as f32 * vegetation_block_size_pixel) as i32;
             let y_pixel = ((y_index - BUFFER) as f32 * vegetation_block_size_pixel) as i32;
 
-            let high_vegetation_density =
-                get_average_pixel_value(&high_vegetation, x_index, y_index, 3);
+            // Cells without any data around, like in the buffer of a missing neighbor, stay white
+            let (Some(high_vegetation_density), Some(medium_vegetation_density)) = (
+                get_average_pixel_value(&high_vegetation, x_index, y_index, 3),
+                get_average_pixel_value(&medium_vegetation, x_index, y_index, 3),
+            ) else {
+                continue;
+            };
 
             if high_vegetation_density < config.yellow_threshold {
                 draw_filled_rect_mut(
@@ -57,15 +77,44 @@ pub fn render_vegetation(
                 );
             }
 
-            let medium_vegetation_density =
-                get_average_pixel_value(&medium_vegetation, x_index, y_index, 3);
-
             let mut green_color: Option<Rgba<u8>> = None;
 
-            // println!("{}", medium_vegetation_density);
+            // Undergrowth under an open canopy slows running without hiding the view, the
+            // density of the green band then comes from it. Cells without canopy are open land.
+            let undergrowth_color = match &undergrowth {
+                Some(undergrowth)
+                    if high_vegetation_density >= config.yellow_threshold
+                        && high_vegetation_density <= config.undergrowth.max_canopy_density =>
+                {
+                    match get_average_pixel_value(undergrowth, x_index, y_index, 3) {
+                        Some(density) if density > config.undergrowth.threshold_2 => Some(GREEN_2),
+                        Some(density) if density > config.undergrowth.threshold_1 => Some(GREEN_1),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
 
             if medium_vegetation_density > config.green_threshold_3 {
                 green_color = Some(GREEN_3);
+            } else if let (Some(color), Some(undergrowth_canvas)) =
+                (undergrowth_color, undergrowth_canvas.as_mut())
+            {
+                // 407 vegetation, slow running, good visibility
+                // 409 vegetation, walk, good visibility
+                let [red, green, blue, _] = color.0;
+                let x = x_pixel as f32;
+                let y = y_pixel as f32;
+                let size = casted_vegetation_block_size_pixel as f32;
+
+                undergrowth_canvas.set_color((red, green, blue));
+                undergrowth_canvas.draw_filled_polygon(&[
+                    (x, y),
+                    (x + size, y),
+                    (x + size, y + size),
+                    (x, y + size),
+                    (x, y),
+                ]);
             } else if medium_vegetation_density > config.green_threshold_2 {
                 green_color = Some(GREEN_2);
             } else if medium_vegetation_density > config.green_threshold_1 {
@@ -91,36 +140,45 @@ pub fn render_vegetation(
     let vegetation_output_path = tile.dir_path.join("vegetation.png");
 
     vegetation_layer_img
-        .save(vegetation_output_path)
-        .expect("could not save output png");
+        .save(&vegetation_output_path)
+        .map_err(image_error(&vegetation_output_path))?;
+
+    if let Some(mut undergrowth_canvas) = undergrowth_canvas {
+        // ISOM undergrowth stripes are vertical, like the north lines
+        undergrowth_canvas.erase_stripes(
+            UNDERGROWTH_LINE_WIDTH * config.dpi_resolution * 10.0 / INCH,
+            UNDERGROWTH_LINE_SPACING * config.dpi_resolution * 10.0 / INCH,
+            StripesDirection::Vertical,
+        );
+
+        undergrowth_canvas.save_as(&tile.dir_path.join("undergrowth.png"))?;
+    }
 
     let duration = start.elapsed();
     println!(" -> Done in {:.1?}", duration);
+
+    Ok(())
 }
 
 fn get_average_pixel_value(
-    tif_image: &TifImage,
+    raster: &Raster,
     x_index: usize,
     y_index: usize,
     distance: usize,
-) -> f64 {
+) -> Option<f64> {
     // TODO: fix this naive averaging function
     let mut count = 0.0;
     let mut sum = 0.0;
-    let width = tif_image.width as usize;
-    let height = tif_image.height as usize;
-
-    if tif_image.pixels.len() == 0 {
-        panic!("Image with no pixels")
-    }
+    let width = raster.width;
+    let height = raster.height;
 
     let min_x = if distance > x_index {
         0
     } else {
         x_index - distance
     };
-    let max_x = if x_index + distance > width as usize {
-        width as usize
+    let max_x = if x_index + distance > width {
+        width
     } else {
         x_index + distance + 1
     };
@@ -129,41 +187,29 @@ fn get_average_pixel_value(
     } else {
         y_index - distance
     };
-    let max_y = if y_index + distance > height as usize {
-        height as usize
+    let max_y = if y_index + distance > height {
+        height
     } else {
         y_index + distance + 1
     };
 
     for x in min_x..max_x {
         for y in min_y..max_y {
+            let value = raster.get(x, y);
+
+            // Missing neighbors leave nodata in the buffer
+            if raster.is_nodata(value) {
+                continue;
+            }
+
             count += 1.0;
-            sum += tif_image.pixels[y * width + x];
+            sum += value;
         }
     }
 
-    return sum / count;
-}
-
-struct TifImage {
-    pixels: Vec<f64>,
-    width: u32,
-    height: u32,
-}
-
-fn get_image_data_from_tif(path: &PathBuf) -> TifImage {
-    let tif_file = File::open(path).expect("Cannot find high vegetation tif image!");
-    let mut img_decoder = Decoder::new(tif_file).expect("Cannot create decoder");
-    img_decoder = img_decoder.with_limits(tiff::decoder::Limits::unlimited());
-    let (width, height) = img_decoder.dimensions().unwrap();
-
-    let DecodingResult::F64(image_data) = img_decoder.read_image().unwrap() else {
-        panic!("Cannot read band data")
-    };
+    if count == 0.0 {
+        return None;
+    }
 
-    return TifImage {
-        pixels: image_data,
-        width,
-        height,
-    };
+    Some(sum / count)
 }
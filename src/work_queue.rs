@@ -0,0 +1,91 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    thread::{spawn, JoinHandle},
+};
+
+/// A blocking multi-consumer queue. Workers wait for new items until the queue is closed.
+pub struct WorkQueue<T> {
+    state: Mutex<WorkQueueState<T>>,
+    condvar: Condvar,
+}
+
+struct WorkQueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+impl<T> WorkQueue<T> {
+    pub fn new() -> WorkQueue<T> {
+        WorkQueue {
+            state: Mutex::new(WorkQueueState {
+                items: VecDeque::new(),
+                closed: false,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        self.state.lock().unwrap().items.push_back(item);
+        self.condvar.notify_one();
+    }
+
+    /// No item will be pushed anymore, workers stop once the queue is empty.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.condvar.notify_all();
+    }
+
+    /// Waits for the next item, returns None when the queue is closed and empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Some(item);
+            }
+
+            if state.closed {
+                return None;
+            }
+
+            state = self.condvar.wait(state).unwrap();
+        }
+    }
+}
+
+impl<T> Default for WorkQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Spawns a pool of threads, each one processing items from the queue until it is closed.
+pub fn spawn_workers<T, F>(
+    queue: &Arc<WorkQueue<T>>,
+    number_of_threads: usize,
+    task: F,
+) -> Vec<JoinHandle<()>>
+where
+    T: Send + 'static,
+    F: Fn(T) + Send + Sync + 'static,
+{
+    let task = Arc::new(task);
+    let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(number_of_threads);
+
+    for _ in 0..number_of_threads.max(1) {
+        let queue = queue.clone();
+        let task = task.clone();
+
+        let spawned_thread = spawn(move || {
+            while let Some(item) = queue.pop() {
+                task(item);
+            }
+        });
+
+        handles.push(spawned_thread);
+    }
+
+    handles
+}